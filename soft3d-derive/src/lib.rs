@@ -0,0 +1,131 @@
+// #[derive(VertexAttribute)] for soft3d-rs, re-exported as
+// soft3d_rs::VertexAttribute next to the trait.
+//
+// Every field is interpolated with its own VertexAttribute impl, so fields
+// can be Vector, f32, arrays of them or other VertexAttribute types. Fields
+// are smooth unless marked with an interpolation qualifier:
+//
+//     #[derive(VertexAttribute)]
+//     struct Varying {
+//         pos: Vector,
+//         #[noperspective]
+//         screen_uv: Vector,
+//         #[flat]
+//         material: u32,
+//     }
+//
+// Flat fields are only copied, they can be of any Clone type like integer
+// ids. noperspective fields must be Clone as well.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Field, Fields, Index, Member};
+
+#[derive(Clone, Copy)]
+enum Interpolation {
+    Smooth,
+    NoPerspective,
+    Flat,
+}
+
+#[proc_macro_derive(VertexAttribute, attributes(flat, noperspective))]
+pub fn derive_vertex_attribute(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(s) => &s.fields,
+        _ => return Err(Error::new_spanned(input, "VertexAttribute can only be derived for structs")),
+    };
+    let fields: Vec<(Member, &Field, Interpolation)> = match fields {
+        Fields::Named(f) => f.named.iter().map(|f| Ok((Member::Named(f.ident.clone().unwrap()), f, interpolation(f)?))).collect::<syn::Result<_>>()?,
+        Fields::Unnamed(f) => f.unnamed.iter().enumerate().map(|(i, f)| Ok((Member::Unnamed(Index::from(i)), f, interpolation(f)?))).collect::<syn::Result<_>>()?,
+        Fields::Unit => Vec::new(),
+    };
+
+    let attr = quote!(::soft3d_rs::VertexAttribute);
+    let clone = quote!(::core::clone::Clone::clone);
+    // Self { member: value, .. } works for tuple structs too, Self { 0: .. }
+    let build = |value: &dyn Fn(&Member, &Field, Interpolation) -> TokenStream2| {
+        let values = fields.iter().map(|(m, f, q)| {
+            let v = value(m, f, *q);
+            quote!(#m: #v)
+        });
+        quote!(Self { #(#values,)* })
+    };
+
+    // Flat fields are only ever copied, with_flat overwrites them in the end
+    let lerp = build(&|m, f, q| {
+        let ty = &f.ty;
+        match q {
+            Interpolation::Flat => quote!(#clone(&a.#m)),
+            _ => quote!(<#ty as #attr>::lerp(&a.#m, &b.#m, t)),
+        }
+    });
+    let scale = build(&|m, f, q| {
+        let ty = &f.ty;
+        match q {
+            Interpolation::Flat => quote!(#clone(&self.#m)),
+            _ => quote!(<#ty as #attr>::scale(&self.#m, s)),
+        }
+    });
+    let perspective_scale = build(&|m, f, q| {
+        let ty = &f.ty;
+        match q {
+            Interpolation::Smooth => quote!(<#ty as #attr>::perspective_scale(&self.#m, s)),
+            _ => quote!(#clone(&self.#m)),
+        }
+    });
+    let with_flat = build(&|m, f, q| {
+        let ty = &f.ty;
+        match q {
+            Interpolation::Flat => quote!(#clone(&provoking.#m)),
+            _ => quote!(<#ty as #attr>::with_flat(self.#m, &provoking.#m)),
+        }
+    });
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #attr for #name #ty_generics #where_clause {
+            fn lerp(a: &Self, b: &Self, t: f32) -> Self {
+                #lerp
+            }
+
+            fn scale(&self, s: f32) -> Self {
+                #scale
+            }
+
+            fn perspective_scale(&self, s: f32) -> Self {
+                #perspective_scale
+            }
+
+            fn with_flat(self, provoking: &Self) -> Self {
+                #with_flat
+            }
+        }
+    })
+}
+
+// The qualifier of a field, #[flat] or #[noperspective], smooth without one
+fn interpolation(field: &Field) -> syn::Result<Interpolation> {
+    let mut found = None;
+    for attr in &field.attrs {
+        let q = if attr.path().is_ident("flat") {
+            Interpolation::Flat
+        } else if attr.path().is_ident("noperspective") {
+            Interpolation::NoPerspective
+        } else {
+            continue;
+        };
+        attr.meta.require_path_only()?;
+        if found.is_some() {
+            return Err(Error::new_spanned(attr, "a field takes at most one interpolation qualifier"));
+        }
+        found = Some(q);
+    }
+    Ok(found.unwrap_or(Interpolation::Smooth))
+}
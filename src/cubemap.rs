@@ -0,0 +1,366 @@
+use image::{DynamicImage, GenericImageView, ImageError};
+use std::convert::TryInto;
+use crate::vector::Vector;
+use crate::texture::{Texture, invalid};
+use crate::sampler::{Sampler, AddressMode, Filter, MipmapMode};
+
+// Faces in the order of CubeTexture::from_faces
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CubeFace {
+    PositiveX = 0,
+    NegativeX = 1,
+    PositiveY = 2,
+    NegativeY = 3,
+    PositiveZ = 4,
+    NegativeZ = 5,
+}
+
+const FACES: [CubeFace; 6] = [
+    CubeFace::PositiveX,
+    CubeFace::NegativeX,
+    CubeFace::PositiveY,
+    CubeFace::NegativeY,
+    CubeFace::PositiveZ,
+    CubeFace::NegativeZ,
+];
+
+impl CubeFace {
+    // Face hit by `dir` and where, s and t in [-1, 1] with the image rows
+    // going down in t, the OpenGL cube map layout
+    fn project(dir: &Vector) -> (CubeFace, f32, f32) {
+        let (ax, ay, az) = (dir.x.abs(), dir.y.abs(), dir.z.abs());
+        let (face, sc, tc, ma) = if ax >= ay && ax >= az {
+            if dir.x >= 0f32 {
+                (CubeFace::PositiveX, -dir.z, -dir.y, ax)
+            } else {
+                (CubeFace::NegativeX, dir.z, -dir.y, ax)
+            }
+        } else if ay >= az {
+            if dir.y >= 0f32 {
+                (CubeFace::PositiveY, dir.x, dir.z, ay)
+            } else {
+                (CubeFace::NegativeY, dir.x, -dir.z, ay)
+            }
+        } else if dir.z >= 0f32 {
+            (CubeFace::PositiveZ, dir.x, -dir.y, az)
+        } else {
+            (CubeFace::NegativeZ, -dir.x, -dir.y, az)
+        };
+        (face, sc / ma, tc / ma)
+    }
+
+    // Inverse of project, s and t may lie outside [-1, 1]
+    fn direction(self, s: f32, t: f32) -> Vector {
+        match self {
+            CubeFace::PositiveX => Vector::vec(1f32, -t, -s),
+            CubeFace::NegativeX => Vector::vec(-1f32, -t, s),
+            CubeFace::PositiveY => Vector::vec(s, 1f32, t),
+            CubeFace::NegativeY => Vector::vec(s, -1f32, -t),
+            CubeFace::PositiveZ => Vector::vec(s, -t, 1f32),
+            CubeFace::NegativeZ => Vector::vec(-s, -t, -1f32),
+        }
+    }
+}
+
+// Six square faces sampled by direction. Filtering is seamless: texels past
+// the edge of a face are read from the neighbouring face.
+pub struct CubeTexture {
+    faces: Vec<Texture>,
+}
+
+// Size of the faces, or why they can't make a cube
+fn face_size(faces: &[DynamicImage; 6]) -> Result<u32, &'static str> {
+    let (w, h) = faces[0].dimensions();
+    if w != h {
+        return Err("cube faces have to be square");
+    }
+    if w == 0 {
+        return Err("cube faces can't be empty");
+    }
+    if !faces.iter().all(|f| f.dimensions() == (w, h)) {
+        return Err("cube faces have to be the same size");
+    }
+    Ok(w)
+}
+
+// Size of the faces of a horizontal cross
+fn cross_size(image: &DynamicImage) -> Result<u32, &'static str> {
+    let n = image.width() / 4;
+    if n == 0 || image.height() / 3 != n {
+        return Err("a horizontal cross is 4 x 3 square faces");
+    }
+    Ok(n)
+}
+
+impl CubeTexture {
+    // Images in the order +X, -X, +Y, -Y, +Z, -Z. Panics unless they are all
+    // square, not empty and of the same size, open_faces returns an error.
+    pub fn from_faces(faces: [DynamicImage; 6]) -> Self {
+        face_size(&faces).unwrap_or_else(|e| panic!("{}", e));
+        CubeTexture {
+            faces: Vec::from(faces).into_iter().map(Texture::new).collect(),
+        }
+    }
+
+    pub fn open_faces(paths: [&str; 6]) -> Result<Self, ImageError> {
+        let mut faces = Vec::with_capacity(6);
+        for path in paths.iter() {
+            faces.push(image::open(path)?);
+        }
+        let faces: [DynamicImage; 6] = faces.try_into().ok().unwrap();
+        face_size(&faces).map_err(invalid)?;
+        Ok(Self::from_faces(faces))
+    }
+
+    // Horizontal cross, 4 x 3 faces:
+    //      +Y
+    //  -X  +Z  +X  -Z
+    //      -Y
+    // Panics when the image is not that shape or smaller than 4 x 3 pixels,
+    // open_cross returns an error.
+    pub fn from_cross(image: &DynamicImage) -> Self {
+        let n = cross_size(image).unwrap_or_else(|e| panic!("{}", e));
+        let rgba = image.to_rgba();
+        let face = |col: u32, row: u32| DynamicImage::ImageRgba8(rgba.view(col * n, row * n, n, n).to_image());
+        Self::from_faces([face(2, 1), face(0, 1), face(1, 0), face(1, 2), face(1, 1), face(3, 1)])
+    }
+
+    pub fn open_cross(path: &str) -> Result<Self, ImageError> {
+        let image = image::open(path)?;
+        cross_size(&image).map_err(invalid)?;
+        Ok(Self::from_cross(&image))
+    }
+
+    // Latitude-longitude panorama, the center of the image is -Z and the top
+    // row +Y. Every face is resampled to `size` x `size` texels, panics when
+    // it is 0, open_equirectangular returns an error.
+    pub fn from_equirectangular(image: DynamicImage, size: u32) -> Self {
+        Self::from_equirectangular_texture(&Texture::new(image), size)
+    }
+
+    // Same from a texture, the faces keep its f32 texels, e.g. for HDR
+    // panoramas
+    pub fn from_equirectangular_texture(source: &Texture, size: u32) -> Self {
+        assert!(size > 0, "cube faces can't be empty");
+        let sampler = Sampler::linear().with_address(AddressMode::Repeat, AddressMode::ClampToEdge);
+        let face = |face: CubeFace| {
+            let mut texels = Vec::with_capacity((size * size * 4) as usize);
+            for y in 0..size {
+                for x in 0..size {
+                    let s = (x as f32 + 0.5f32) / size as f32 * 2f32 - 1f32;
+                    let t = (y as f32 + 0.5f32) / size as f32 * 2f32 - 1f32;
+                    let dir = face.direction(s, t).normalize();
+                    let lon = dir.x.atan2(-dir.z);
+                    let lat = dir.y.clamp(-1f32, 1f32).asin();
+                    let uv = Vector::vec2(0.5f32 + lon / (2f32 * std::f32::consts::PI), 0.5f32 + lat / std::f32::consts::PI);
+                    let c = source.sample(&sampler, &uv);
+                    texels.extend_from_slice(&[c.x, c.y, c.z, c.w]);
+                }
+            }
+            Texture::from_rgba(size, size, texels)
+        };
+        CubeTexture {
+            faces: FACES.iter().map(|&f| face(f)).collect(),
+        }
+    }
+
+    pub fn open_equirectangular(path: &str, size: u32) -> Result<Self, ImageError> {
+        if size == 0 {
+            return Err(invalid("cube faces can't be empty"));
+        }
+        let image = image::open(path)?;
+        if image.width() == 0 || image.height() == 0 {
+            return Err(invalid("the panorama is empty"));
+        }
+        Ok(Self::from_equirectangular(image, size))
+    }
+
+    pub fn levels(&self) -> usize {
+        self.faces[0].levels()
+    }
+
+    pub fn face(&self, face: CubeFace) -> &Texture {
+        &self.faces[face as usize]
+    }
+
+    // Texel (x, y) of a face, texels past an edge come from the face beyond it
+    fn fetch(&self, level: usize, face: CubeFace, x: i64, y: i64) -> Vector {
+        let n = self.faces[0].size(level).0 as i64;
+        if x >= 0 && x < n && y >= 0 && y < n {
+            return self.faces[face as usize].texel(level, x as u32, y as u32);
+        }
+        let to_st = |i: i64| (i as f32 + 0.5f32) / n as f32 * 2f32 - 1f32;
+        let (face, s, t) = CubeFace::project(&face.direction(to_st(x), to_st(y)));
+        let to_texel = |st: f32| (((st + 1f32) * 0.5f32 * n as f32).floor() as i64).clamp(0, n - 1);
+        self.faces[face as usize].texel(level, to_texel(s) as u32, to_texel(t) as u32)
+    }
+
+    fn filter(&self, level: usize, dir: &Vector, filter: Filter) -> Vector {
+        let n = self.faces[0].size(level).0 as f32;
+        let (face, s, t) = CubeFace::project(dir);
+        let (fx, fy) = ((s + 1f32) * 0.5f32 * n, (t + 1f32) * 0.5f32 * n);
+        match filter {
+            Filter::Nearest => {
+                let last = n as i64 - 1;
+                self.fetch(level, face, (fx.floor() as i64).min(last), (fy.floor() as i64).min(last))
+            }
+            Filter::Linear => {
+                let (fx, fy) = (fx - 0.5f32, fy - 0.5f32);
+                let (x0, y0) = (fx.floor(), fy.floor());
+                let (dx, dy) = (fx - x0, fy - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+                let c0 = Vector::lerp(&self.fetch(level, face, x0, y0), &self.fetch(level, face, x0 + 1, y0), dx);
+                let c1 = Vector::lerp(&self.fetch(level, face, x0, y0 + 1), &self.fetch(level, face, x0 + 1, y0 + 1), dx);
+                Vector::lerp(&c0, &c1, dy)
+            }
+        }
+    }
+
+    // Samples along `dir` (any length) on level 0, the address modes of the
+    // sampler are not used
+    pub fn sample(&self, sampler: &Sampler, dir: &Vector) -> Vector {
+        self.sample_level(sampler, dir, 0f32)
+    }
+
+    // Samples with an explicit LOD, clamped to the mip chain, level 0 when
+    // it is not finite like Texture::sample_level
+    pub fn sample_level(&self, sampler: &Sampler, dir: &Vector, lod: f32) -> Vector {
+        let lod = if lod.is_finite() { lod } else { 0f32 };
+        let filter = if lod > 0f32 { sampler.min_filter } else { sampler.mag_filter };
+        let lod = lod.clamp(0f32, (self.levels() - 1) as f32);
+        match sampler.mipmap {
+            MipmapMode::None => self.filter(0, dir, filter),
+            MipmapMode::Nearest => self.filter(lod.round() as usize, dir, filter),
+            MipmapMode::Linear => {
+                let l0 = lod.floor() as usize;
+                let t = lod - l0 as f32;
+                if t == 0f32 {
+                    return self.filter(l0, dir, filter);
+                }
+                Vector::lerp(&self.filter(l0, dir, filter), &self.filter(l0 + 1, dir, filter), t)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cubemap::{CubeTexture, CubeFace, FACES, face_size, cross_size};
+    use crate::texture::Texture;
+    use crate::sampler::Sampler;
+    use crate::vector::Vector;
+    use image::{DynamicImage, ImageError, RgbaImage, Rgba};
+
+    // Every face a solid color, red channel is the face index * 40
+    fn solid() -> CubeTexture {
+        CubeTexture::from_faces(FACES.map(|f| {
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, Rgba([f as u8 * 40, 0, 0, 255])))
+        }))
+    }
+
+    fn red(c: Vector) -> u8 {
+        (c.x * 255.0).round() as u8
+    }
+
+    #[test]
+    fn project_and_back() {
+        for &face in FACES.iter() {
+            for &(s, t) in &[(0.0, 0.0), (0.5, -0.25), (-0.9, 0.7)] {
+                let (f, s2, t2) = CubeFace::project(&face.direction(s, t).scale(3.0));
+                assert_eq!(face, f);
+                assert!((s - s2).abs() < 1e-6 && (t - t2).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn faces_by_direction() {
+        let cube = solid();
+        let sampler = Sampler::nearest();
+        let dirs = [
+            Vector::vec(1.0, 0.2, 0.1), Vector::vec(-1.0, 0.2, 0.1), Vector::vec(0.1, 1.0, 0.2),
+            Vector::vec(0.1, -1.0, 0.2), Vector::vec(0.1, 0.2, 1.0), Vector::vec(0.1, 0.2, -1.0),
+        ];
+        for (i, dir) in dirs.iter().enumerate() {
+            assert_eq!(i as u8 * 40, red(cube.sample(&sampler, dir)));
+        }
+    }
+
+    #[test]
+    fn seamless_edges() {
+        let cube = solid();
+        let sampler = Sampler::linear();
+        // On the edge between +X and +Z both faces weigh the same from either side
+        let a = cube.sample(&sampler, &Vector::vec(1.0, 0.0, 0.999));
+        let b = cube.sample(&sampler, &Vector::vec(0.999, 0.0, 1.0));
+        assert_eq!(160 / 2, red(a.clone()));
+        assert!((a.x - b.x).abs() < 2.0 / 255.0);
+        // Half a texel inside the face there is no bleeding
+        assert_eq!(0, red(cube.sample(&sampler, &Vector::vec(1.0, 0.0, 0.74))));
+    }
+
+    #[test]
+    fn cross_layout() {
+        let colors = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 0], [0, 255, 255], [255, 0, 255]];
+        // Cells of the faces in a 4 x 3 cross, same order as FACES
+        let cells = [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (3, 1)];
+        let image = RgbaImage::from_fn(8, 6, |x, y| {
+            match cells.iter().position(|&c| c == (x / 2, y / 2)) {
+                Some(i) => Rgba([colors[i][0], colors[i][1], colors[i][2], 255]),
+                None => Rgba([0, 0, 0, 0]),
+            }
+        });
+        let cube = CubeTexture::from_cross(&DynamicImage::ImageRgba8(image));
+        for (i, &face) in FACES.iter().enumerate() {
+            let c = cube.face(face).sample(&Sampler::nearest(), &Vector::vec2(0.5, 0.5));
+            assert_eq!(colors[i][0] as f32 / 255.0, c.x);
+            assert_eq!(colors[i][2] as f32 / 255.0, c.z);
+        }
+    }
+
+    #[test]
+    fn bad_dimensions() {
+        let image = |w, h| DynamicImage::ImageRgba8(RgbaImage::new(w, h));
+        assert!(face_size(&[(); 6].map(|_| image(4, 4))).is_ok());
+        assert!(face_size(&[(); 6].map(|_| image(4, 3))).is_err());
+        assert!(face_size(&[(); 6].map(|_| image(0, 0))).is_err());
+        assert!(face_size(&[0, 0, 0, 0, 0, 1].map(|i| image(4 + i, 4 + i))).is_err());
+        assert_eq!(Ok(2), cross_size(&image(8, 6)));
+        assert!(cross_size(&image(8, 9)).is_err());
+        assert!(cross_size(&image(3, 2)).is_err());
+
+        // A bad file is an error, not a panic
+        let path = std::env::temp_dir().join(format!("soft3d-cross-{}.png", std::process::id()));
+        image(3, 2).save(&path).unwrap();
+        let cube = CubeTexture::open_cross(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(cube, Err(ImageError::Parameter(_))));
+    }
+
+    #[test]
+    fn equirectangular_hdr_and_size() {
+        // Texels over 1 survive the resampling
+        let source = Texture::from_rgba(8, 4, [4.0, 0.5, 0.25, 1.0].repeat(8 * 4));
+        let cube = CubeTexture::from_equirectangular_texture(&source, 4);
+        let c = cube.sample(&Sampler::nearest(), &Vector::vec(0.3, 0.6, -1.0));
+        assert_eq!(Vector::new(4.0, 0.5, 0.25, 1.0), c);
+
+        let path = std::env::temp_dir().join(format!("soft3d-panorama-{}.png", std::process::id()));
+        DynamicImage::ImageRgba8(RgbaImage::new(8, 4)).save(&path).unwrap();
+        let cube = CubeTexture::open_equirectangular(path.to_str().unwrap(), 0);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(cube, Err(ImageError::Parameter(_))));
+    }
+
+    #[test]
+    fn equirectangular_poles_and_horizon() {
+        // Top half white, bottom half black
+        let image = RgbaImage::from_fn(16, 8, |_, y| if y < 4 { Rgba([255, 255, 255, 255]) } else { Rgba([0, 0, 0, 255]) });
+        let cube = CubeTexture::from_equirectangular(DynamicImage::ImageRgba8(image), 8);
+        let sampler = Sampler::nearest();
+        assert_eq!(255, red(cube.sample(&sampler, &Vector::vec(0.0, 1.0, 0.0))));
+        assert_eq!(0, red(cube.sample(&sampler, &Vector::vec(0.0, -1.0, 0.0))));
+        assert_eq!(255, red(cube.sample(&sampler, &Vector::vec(0.3, 0.6, -1.0))));
+        assert_eq!(0, red(cube.sample(&sampler, &Vector::vec(-1.0, -0.6, 0.3))));
+    }
+}
@@ -0,0 +1,155 @@
+use std::f32::consts::PI;
+
+// Reconstruction filter used to bring a supersampled image down to the
+// output size, see Renderer::set_supersampling
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DownsampleFilter {
+    // Average of the k x k block of each output pixel
+    #[default]
+    Box,
+    // Triangle two output pixels wide, softer than Box
+    Tent,
+    // Lanczos with 3 lobes, sharpest, can ring a little at hard edges
+    Lanczos,
+}
+
+impl DownsampleFilter {
+    // Half width in output pixels
+    fn radius(self) -> f32 {
+        match self {
+            DownsampleFilter::Box => 0.5f32,
+            DownsampleFilter::Tent => 1f32,
+            DownsampleFilter::Lanczos => 3f32,
+        }
+    }
+
+    // Weight at distance `d` in output pixels
+    fn weight(self, d: f32) -> f32 {
+        let d = d.abs();
+        match self {
+            DownsampleFilter::Box => if d < 0.5f32 { 1f32 } else { 0f32 },
+            DownsampleFilter::Tent => (1f32 - d).max(0f32),
+            DownsampleFilter::Lanczos => {
+                if d < 1e-6 {
+                    1f32
+                } else if d < 3f32 {
+                    let x = PI * d;
+                    3f32 * x.sin() * (x / 3f32).sin() / (x * x)
+                } else {
+                    0f32
+                }
+            }
+        }
+    }
+
+    // (first source pixel, normalized weights) of every output pixel of an
+    // axis `k` times smaller than `size`
+    fn taps(self, size: usize, k: usize) -> Vec<(usize, Vec<f32>)> {
+        let out = size / k;
+        let reach = (self.radius() * k as f32).ceil() as i64;
+        (0..out).map(|o| {
+            let center = (o as f32 + 0.5f32) * k as f32;
+            let first = (center as i64 - reach).max(0);
+            let last = (center as i64 + reach).min(size as i64);
+            let mut w: Vec<f32> = (first..last)
+                .map(|i| self.weight((i as f32 + 0.5f32 - center) / k as f32))
+                .collect();
+            //去掉两端权重为0的像素
+            let skip = w.iter().take_while(|&&w| w == 0f32).count();
+            w.drain(..skip);
+            while w.last() == Some(&0f32) {
+                w.pop();
+            }
+            let sum: f32 = w.iter().sum();
+            w.iter_mut().for_each(|w| *w /= sum);
+            (first as usize + skip, w)
+        }).collect()
+    }
+
+    // Shrinks an RGBA f32 image of `width` x `height` by `k` on both axes,
+    // separably, rows first
+    pub(crate) fn apply(self, src: &[f32], width: usize, height: usize, k: usize) -> Vec<f32> {
+        let mut buffers = DownsampleBuffers::default();
+        self.apply_into(src, width, height, k, &mut buffers);
+        buffers.out
+    }
+
+    // apply into `buffers`, which keep their allocations from call to call
+    pub(crate) fn apply_into<'a>(self, src: &[f32], width: usize, height: usize, k: usize, buffers: &'a mut DownsampleBuffers) -> &'a [f32] {
+        let (out_w, out_h) = (width / k, height / k);
+        let DownsampleBuffers { rows, out } = buffers;
+        rows.clear();
+        rows.resize(out_w * height * 4, 0f32);
+        for (x, (first, w)) in self.taps(width, k).iter().enumerate() {
+            for y in 0..height {
+                for (j, w) in w.iter().enumerate() {
+                    let s = (y * width + first + j) * 4;
+                    for c in 0..4 {
+                        rows[(y * out_w + x) * 4 + c] += src[s + c] * w;
+                    }
+                }
+            }
+        }
+
+        out.clear();
+        out.resize(out_w * out_h * 4, 0f32);
+        for (y, (first, w)) in self.taps(height, k).iter().enumerate() {
+            for x in 0..out_w {
+                for (j, w) in w.iter().enumerate() {
+                    let s = ((first + j) * out_w + x) * 4;
+                    for c in 0..4 {
+                        out[(y * out_w + x) * 4 + c] += rows[s + c] * w;
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+// The intermediate and output images of DownsampleFilter::apply_into
+#[derive(Default)]
+pub(crate) struct DownsampleBuffers {
+    rows: Vec<f32>,
+    out: Vec<f32>,
+}
+
+#[cfg(test)]
+mod test {
+    use crate::downsample::DownsampleFilter;
+
+    const FILTERS: [DownsampleFilter; 3] = [DownsampleFilter::Box, DownsampleFilter::Tent, DownsampleFilter::Lanczos];
+
+    #[test]
+    fn weights_are_normalized() {
+        for f in FILTERS.iter() {
+            for (_, w) in f.taps(48, 4) {
+                assert!((w.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+            }
+        }
+        // The box of output pixel 1 is source pixels 3..6
+        assert_eq!((3, vec![1.0 / 3.0; 3]), DownsampleFilter::Box.taps(9, 3)[1]);
+    }
+
+    #[test]
+    fn constant_stays_constant() {
+        let src = [0.25f32, 0.5, 1.0, 1.0].repeat(16 * 8);
+        for f in FILTERS.iter() {
+            let out = f.apply(&src, 16, 8, 4);
+            assert_eq!(4 * 2 * 4, out.len());
+            assert!(out.chunks(4).all(|c| c.iter().zip(&src).all(|(a, b)| (a - b).abs() < 1e-5)));
+        }
+    }
+
+    #[test]
+    fn box_averages_blocks() {
+        // Left half 0, right half 1, the middle output pixel straddles both
+        let src: Vec<f32> = (0..6 * 2).flat_map(|i| {
+            let v = if i % 6 >= 3 { 1.0 } else { 0.0 };
+            vec![v, v, v, 1.0]
+        }).collect();
+        let out = DownsampleFilter::Box.apply(&src, 6, 2, 2);
+        let red: Vec<f32> = out.chunks(4).map(|c| c[0]).collect();
+        assert_eq!(vec![0.0, 0.5, 1.0], red);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::vector::Vector;
+
+// How a color attachment stores its pixels. Every format is kept as four
+// f32 per pixel holding the value the format can represent, so blending and
+// later passes see the real precision and range. Conversion to 8 bits only
+// happens when the attachment is presented (color_image, get_color_buffer).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ColorFormat {
+    // 8 bits per channel, alpha reads back as 1
+    #[default]
+    Rgb8,
+    Rgba8,
+    // sRGB encoded color, linear alpha. Shaders write and blend linear
+    // values, the encoding is applied on store.
+    Srgb8Alpha8,
+    // Half floats
+    Rgba16F,
+    Rgba32F,
+    // Red only, green and blue read back as 0 and alpha as 1
+    R32F,
+}
+
+impl ColorFormat {
+    pub fn has_alpha(self) -> bool {
+        !matches!(self, ColorFormat::Rgb8 | ColorFormat::R32F)
+    }
+
+    // Can hold values outside [0, 1]
+    pub fn is_float(self) -> bool {
+        matches!(self, ColorFormat::Rgba16F | ColorFormat::Rgba32F | ColorFormat::R32F)
+    }
+
+    // `color` rounded to what the format keeps
+    pub(crate) fn store(self, color: &Vector) -> Vector {
+        match self {
+            ColorFormat::Rgb8 => Vector::new(unorm8(color.x), unorm8(color.y), unorm8(color.z), 1f32),
+            ColorFormat::Rgba8 => Vector::new(unorm8(color.x), unorm8(color.y), unorm8(color.z), unorm8(color.w)),
+            ColorFormat::Srgb8Alpha8 => Vector::new(srgb8(color.x), srgb8(color.y), srgb8(color.z), round8(color.w)),
+            ColorFormat::Rgba16F => Vector::new(half(color.x), half(color.y), half(color.z), half(color.w)),
+            ColorFormat::Rgba32F => color.clone(),
+            ColorFormat::R32F => Vector::new(color.x, 0f32, 0f32, 1f32),
+        }
+    }
+
+    // Stored RGBA f32 pixels as RGB24 for display into `bytes`, which keeps
+    // its allocation
+    pub(crate) fn present_rgb(self, colors: &[f32], bytes: &mut Vec<u8>) {
+        bytes.resize(colors.len() / 4 * 3, 0);
+        for (rgb, c) in bytes.chunks_exact_mut(3).zip(colors.chunks_exact(4)) {
+            rgb.copy_from_slice(&self.present(c)[..3]);
+        }
+    }
+
+    pub(crate) fn present_rgba(self, colors: &[f32]) -> Vec<u8> {
+        colors.chunks_exact(4).flat_map(|c| self.present(c)).collect()
+    }
+
+    // A stored pixel as 8-bit RGBA for display, clamped to [0, 1]
+    pub(crate) fn present(self, c: &[f32]) -> [u8; 4] {
+        let byte = |v: f32| (v.clamp(0f32, 1f32) * 255f32).round() as u8;
+        match self {
+            ColorFormat::Srgb8Alpha8 => [byte(linear_to_srgb(c[0])), byte(linear_to_srgb(c[1])), byte(linear_to_srgb(c[2])), byte(c[3])],
+            _ => [byte(c[0]), byte(c[1]), byte(c[2]), byte(c[3])],
+        }
+    }
+}
+
+// Truncated like a `as u8` cast, out of range values saturate
+#[inline]
+fn unorm8(v: f32) -> f32 {
+    (v * 255f32) as u8 as f32 / 255f32
+}
+
+// Rounded like the sRGB channels next to it
+#[inline]
+fn round8(v: f32) -> f32 {
+    (v.clamp(0f32, 1f32) * 255f32).round() / 255f32
+}
+
+#[inline]
+fn srgb8(v: f32) -> f32 {
+    srgb_to_linear((linear_to_srgb(v.clamp(0f32, 1f32)) * 255f32).round() / 255f32)
+}
+
+pub(crate) fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308f32 {
+        v * 12.92f32
+    } else {
+        1.055f32 * v.powf(1f32 / 2.4f32) - 0.055f32
+    }
+}
+
+pub(crate) fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045f32 {
+        v / 12.92f32
+    } else {
+        ((v + 0.055f32) / 1.055f32).powf(2.4f32)
+    }
+}
+
+// Nearest value an IEEE half float holds, round to nearest even. Values
+// past the largest half (65504) become infinite.
+fn half(v: f32) -> f32 {
+    const MAX: f32 = 65504f32;
+    // Smallest normal half, below it the spacing is fixed at 2^-24
+    const MIN_NORMAL: f32 = 1f32 / 16384f32;
+    if !v.is_finite() {
+        return v;
+    }
+    if v.abs() < MIN_NORMAL {
+        let step = 1f32 / 16777216f32;
+        return (v / step).round_ties_even() * step;
+    }
+    //丢掉尾数低13位, 就近舍入到偶数
+    let bits = v.to_bits();
+    let rounded = f32::from_bits((bits + 0x0fff + ((bits >> 13) & 1)) & !0x1fff);
+    if rounded.abs() > MAX {
+        f32::INFINITY.copysign(v)
+    } else {
+        rounded
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::format::{ColorFormat, half, linear_to_srgb, srgb_to_linear};
+    use crate::vector::Vector;
+
+    #[test]
+    fn unorm8_truncates_and_saturates() {
+        let c = ColorFormat::Rgb8.store(&Vector::new(0.5, 1.5, -0.5, 0.3));
+        assert_eq!(Vector::new(127.0 / 255.0, 1.0, 0.0, 1.0), c);
+        let c = ColorFormat::Rgba8.store(&Vector::new(0.5, 1.5, -0.5, 0.3));
+        assert_eq!(76.0 / 255.0, c.w);
+        assert_eq!([127, 255, 0, 76], ColorFormat::Rgba8.present(&[c.x, c.y, c.z, c.w]));
+    }
+
+    #[test]
+    fn float_formats_keep_range() {
+        let hdr = Vector::new(4.25, -2.0, 1e-3, 0.5);
+        assert_eq!(hdr, ColorFormat::Rgba32F.store(&hdr));
+        assert_eq!(Vector::new(4.25, 0.0, 0.0, 1.0), ColorFormat::R32F.store(&hdr));
+        assert_eq!([255, 0, 0, 255], ColorFormat::R32F.present(&[4.25, 0.0, 0.0, 1.0]));
+    }
+
+    #[test]
+    fn half_precision() {
+        assert_eq!(1.0, half(1.0 + 1.0 / 4096.0));
+        assert_eq!(1.0 + 1.0 / 1024.0, half(1.0 + 1.0 / 1024.0));
+        // Exactly halfway, rounds to the even mantissa
+        assert_eq!(1.0, half(1.0 + 1.0 / 2048.0));
+        assert_eq!(1.0 + 2.0 / 1024.0, half(1.0 + 3.0 / 2048.0));
+        assert_eq!(65504.0, half(65504.0));
+        assert_eq!(f32::INFINITY, half(70000.0));
+        assert_eq!(-f32::INFINITY, half(-70000.0));
+        assert_eq!(0.0, half(1e-8));
+        assert_eq!(1.0 / 16777216.0, half(1.2 / 16777216.0));
+    }
+
+    #[test]
+    fn srgb() {
+        for i in 0..=255 {
+            let v = i as f32 / 255.0;
+            assert!((linear_to_srgb(srgb_to_linear(v)) - v).abs() < 1e-5);
+        }
+        // Linear 0.5 is sRGB 188, alpha is rounded like the color
+        let c = ColorFormat::Srgb8Alpha8.store(&Vector::new(0.5, 0.0, 1.0, 0.5));
+        assert_eq!([188, 0, 255, 128], ColorFormat::Srgb8Alpha8.present(&[c.x, c.y, c.z, c.w]));
+    }
+}
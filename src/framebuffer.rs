@@ -0,0 +1,234 @@
+use crate::texture::Texture;
+use crate::vector::Vector;
+use crate::format::ColorFormat;
+use crate::downsample::DownsampleFilter;
+use image::{ImageBuffer, RgbImage, RgbaImage, GrayImage, Luma};
+
+// Most color outputs a fragment shader can write, see FragmentOutput
+pub const MAX_COLOR_ATTACHMENTS: usize = 4;
+
+// Supported samples per pixel
+pub const SAMPLE_COUNTS: [usize; 4] = [1, 2, 4, 8];
+
+// A render target: color attachments, a depth attachment and a stencil
+// attachment, all of the same size. Bind it with Renderer::bind_framebuffer,
+// fragment shader output i goes to color attachment i.
+//
+// A multisampled framebuffer keeps color, depth and stencil per sample.
+// Draws write the samples and resolve them into the color attachments, which
+// is what every color accessor returns.
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    samples: usize,
+    // RGBA f32 per pixel whatever the format, see ColorFormat
+    pub(crate) colors: Vec<Vec<f32>>,
+    // RGBA f32 per sample, empty when not multisampled
+    pub(crate) sample_colors: Vec<Vec<f32>>,
+    pub(crate) formats: Vec<ColorFormat>,
+    // Per sample
+    pub(crate) depth: Vec<f32>,
+    pub(crate) stencil: Vec<u8>,
+}
+
+impl Framebuffer {
+    // `color_attachments` attachments of the default format, RGB8
+    pub fn new(width: usize, height: usize, color_attachments: usize) -> Self {
+        Self::with_formats(width, height, &vec![ColorFormat::default(); color_attachments])
+    }
+
+    // One color attachment per format
+    pub fn with_formats(width: usize, height: usize, formats: &[ColorFormat]) -> Self {
+        assert!(formats.len() <= MAX_COLOR_ATTACHMENTS, "at most {} color attachments", MAX_COLOR_ATTACHMENTS);
+        Framebuffer {
+            width,
+            height,
+            samples: 1,
+            colors: formats.iter().map(|f| Self::cleared(*f, width * height, &Vector::zero())).collect(),
+            sample_colors: Vec::new(),
+            formats: formats.to_vec(),
+            depth: vec![-f32::INFINITY; width * height],
+            stencil: vec![0u8; width * height],
+        }
+    }
+
+    // Multisampled with `samples` per pixel, one of SAMPLE_COUNTS. The content
+    // is cleared.
+    pub fn with_samples(self, samples: usize) -> Self {
+        assert!(SAMPLE_COUNTS.contains(&samples), "unsupported sample count {}", samples);
+        let pixels = self.width * self.height;
+        let sample_colors = if samples > 1 { vec![vec![0f32; pixels * samples * 4]; self.colors.len()] } else { Vec::new() };
+        let mut fb = Framebuffer {
+            samples,
+            sample_colors,
+            depth: vec![0f32; pixels * samples],
+            stencil: vec![0u8; pixels * samples],
+            ..self
+        };
+        fb.clear((0, 0, fb.width, fb.height), &Vector::zero(), -f32::INFINITY, 0);
+        fb
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    pub fn color_attachments(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn format(&self, attachment: usize) -> ColorFormat {
+        self.formats[attachment]
+    }
+
+    // RGBA f32 as stored, row by row from the top. Not clamped for float
+    // formats, linear for sRGB.
+    pub fn color_buffer(&self, attachment: usize) -> &[f32] {
+        &self.colors[attachment]
+    }
+
+    // The attachment converted to RGB24 for display
+    pub fn color_bytes(&self, attachment: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.formats[attachment].present_rgb(&self.colors[attachment], &mut bytes);
+        bytes
+    }
+
+    // Per sample when multisampled
+    pub fn depth_buffer(&self) -> &[f32] {
+        &self.depth
+    }
+
+    pub fn stencil_buffer(&self) -> &[u8] {
+        &self.stencil
+    }
+
+    pub fn color_image(&self, attachment: usize) -> RgbImage {
+        ImageBuffer::from_raw(self.width as u32, self.height as u32, self.color_bytes(attachment)).unwrap()
+    }
+
+    pub fn color_image_rgba(&self, attachment: usize) -> RgbaImage {
+        let bytes = self.formats[attachment].present_rgba(&self.colors[attachment]);
+        ImageBuffer::from_raw(self.width as u32, self.height as u32, bytes).unwrap()
+    }
+
+    // Clears every sample of the pixels in `rect`, (x0, y0, x1, y1) with
+    // exclusive end
+    pub(crate) fn clear(&mut self, rect: (usize, usize, usize, usize), color: &Vector, depth: f32, stencil: u8) {
+        let (x0, y0, x1, y1) = rect;
+        let (w, n) = (self.width, self.samples);
+        for (i, format) in self.formats.iter().enumerate() {
+            let c = format.store(color);
+            let c = [c.x, c.y, c.z, c.w];
+            for y in y0..y1 {
+                for px in self.colors[i][(y * w + x0) * 4..(y * w + x1) * 4].chunks_exact_mut(4) {
+                    px.copy_from_slice(&c);
+                }
+                if n > 1 {
+                    for px in self.sample_colors[i][(y * w + x0) * n * 4..(y * w + x1) * n * 4].chunks_exact_mut(4) {
+                        px.copy_from_slice(&c);
+                    }
+                }
+            }
+        }
+        for y in y0..y1 {
+            let row = (y * w + x0) * n..(y * w + x1) * n;
+            self.depth[row.clone()].fill(depth);
+            self.stencil[row].fill(stencil);
+        }
+    }
+
+    // Writes sample `s` of a pixel, resolve afterwards when multisampled
+    pub(crate) fn set_sample_color(&mut self, attachment: usize, x: usize, y: usize, s: usize, color: &Vector) {
+        let c = self.formats[attachment].store(color);
+        let pos = ((y * self.width + x) * self.samples + s) * 4;
+        let cb = if self.samples > 1 { &mut self.sample_colors[attachment] } else { &mut self.colors[attachment] };
+        cb[pos..pos + 4].copy_from_slice(&[c.x, c.y, c.z, c.w]);
+    }
+
+    // Averages the samples of the pixels in `rect` into the color
+    // attachments, a draw only resolves what it may have touched. Float and
+    // sRGB formats average the stored linear values.
+    pub(crate) fn resolve(&mut self, rect: (usize, usize, usize, usize)) {
+        let (x0, y0, x1, y1) = rect;
+        if self.samples == 1 || x0 >= x1 || y0 >= y1 {
+            return;
+        }
+        let (w, n) = (self.width, self.samples);
+        for (cb, samples) in self.colors.iter_mut().zip(&self.sample_colors) {
+            for y in y0..y1 {
+                let pixels = cb[(y * w + x0) * 4..(y * w + x1) * 4].chunks_exact_mut(4);
+                for (c, s) in pixels.zip(samples[(y * w + x0) * n * 4..(y * w + x1) * n * 4].chunks_exact(4 * n)) {
+                    for (ch, c) in c.iter_mut().enumerate() {
+                        *c = s.iter().skip(ch).step_by(4).sum::<f32>() / n as f32;
+                    }
+                }
+            }
+        }
+    }
+
+    fn cleared(format: ColorFormat, pixels: usize, color: &Vector) -> Vec<f32> {
+        let c = format.store(color);
+        [c.x, c.y, c.z, c.w].repeat(pixels)
+    }
+
+    // `k` times smaller on both axes, the color attachments are filtered with
+    // `filter`. Depth and stencil are taken from the pixel in the middle of
+    // each k x k block.
+    pub(crate) fn downsample(&self, k: usize, filter: DownsampleFilter) -> Framebuffer {
+        let (width, height) = (self.width / k, self.height / k);
+        let mut fb = Framebuffer::with_formats(width, height, &self.formats);
+        for (dst, src) in fb.colors.iter_mut().zip(&self.colors) {
+            *dst = filter.apply(src, self.width, self.height, k);
+        }
+        for y in 0..height {
+            for x in 0..width {
+                let i = ((y * k + k / 2) * self.width + x * k + k / 2) * self.samples;
+                fb.depth[y * width + x] = self.depth[i];
+                fb.stencil[y * width + x] = self.stencil[i];
+            }
+        }
+        fb
+    }
+
+    // Nearest is black, the farthest written depth white, pixels still at
+    // `clear_depth` are white too. Sample 0 when multisampled.
+    pub fn depth_image(&self, clear_depth: f32) -> GrayImage {
+        let cleared = |d: f32| !d.is_finite() || d == clear_depth;
+        let (min, max) = self.depth.iter().step_by(self.samples)
+            .filter(|&&d| !cleared(d))
+            .fold((f32::INFINITY, -f32::INFINITY), |(min, max), &d| (min.min(d), max.max(d)));
+        let range = if max > min { max - min } else { 1f32 };
+
+        ImageBuffer::from_fn(self.width as u32, self.height as u32, |x, y| {
+            let d = self.depth[(self.width * y as usize + x as usize) * self.samples];
+            if !cleared(d) {
+                Luma([((d - min) / range * 255f32) as u8])
+            } else {
+                Luma([255u8])
+            }
+        })
+    }
+
+    // The attachment as a texture for a later pass, with the stored values so
+    // float formats keep their range. The top row of the framebuffer is v = 1.
+    // Panics when the framebuffer is empty, like Texture::from_rgba.
+    pub fn color_texture(&self, attachment: usize) -> Texture {
+        Texture::from_rgba(self.width as u32, self.height as u32, self.colors[attachment].clone())
+    }
+
+    // The depth in every channel at full precision, e.g. for shadow maps.
+    // Sample 0 when multisampled, panics when the framebuffer is empty.
+    pub fn depth_texture(&self) -> Texture {
+        let texels = self.depth.iter().step_by(self.samples).flat_map(|&d| [d, d, d, 1f32]).collect();
+        Texture::from_rgba(self.width as u32, self.height as u32, texels)
+    }
+}
@@ -0,0 +1,36 @@
+// so that #[derive(VertexAttribute)] works inside the crate too
+extern crate self as soft3d_rs;
+
+mod vector;
+mod vertex;
+mod matrix;
+mod renderer;
+mod texture;
+mod sampler;
+mod cubemap;
+mod raster;
+mod tile;
+mod framebuffer;
+mod format;
+mod downsample;
+mod state;
+mod topology;
+mod shader;
+
+pub use crate::vector::Vector;
+pub use crate::vertex::{Vertex, VertexAttribute};
+pub use soft3d_derive::VertexAttribute;
+pub use crate::matrix::Matrix;
+pub use crate::renderer::{Renderer, ShaderRenderer, VSOutput, FragmentInput, FragmentOutput};
+pub use crate::shader::Shader;
+pub use crate::framebuffer::{Framebuffer, MAX_COLOR_ATTACHMENTS, SAMPLE_COUNTS};
+pub use crate::format::ColorFormat;
+pub use crate::downsample::DownsampleFilter;
+pub use crate::texture::Texture;
+pub use crate::cubemap::{CubeTexture, CubeFace};
+pub use crate::sampler::{Sampler, AddressMode, Filter, MipmapMode};
+pub use crate::state::{BlendState, BlendFactor, BlendOp, DepthState, CompareFunc, StencilState, StencilFaceState, StencilOp,
+                          RasterizerState, CullMode, FrontFace, MultisampleState, Viewport};
+pub use crate::topology::PrimitiveTopology;
+
+pub mod scene;
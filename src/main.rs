@@ -1,54 +1,19 @@
-extern crate core;
-
-mod vector;
-mod vertex;
-mod matrix;
-mod renderer;
-mod texture;
-
-
-use crate::vertex::Vertex;
-use crate::vector::Vector;
-use crate::renderer::{Renderer, VSOutput};
-use crate::matrix::Matrix;
+use soft3d_rs::{ShaderRenderer, Texture, scene};
 use sdl2::pixels::PixelFormatEnum;
-use sdl2::event::Event;
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
-use std::f32;
-
-use std::time::{Duration, SystemTime};
-use crate::texture::Texture;
+use std::time::SystemTime;
 
 fn main() -> Result<(), String> {
-    let (w, h) = (800, 600);
+    let (mut w, mut h) = (800, 600);
     let tex = Texture::open("./img.jpg").expect("无法打开图片");
 
-    let fs = move|f: &Vertex| -> Vector{
-        tex.get_color_linear(f.uv.x, f.uv.y)
-    };
+    let shader = scene::TexturedShader { texture: tex };
 
     //Vertices
-    let data = vec![
-        Vertex{pos:Vector::point(-1.0,-1.0,1.0),uv: Vector::vec2(1.0,0.0),color: Vector::new(1.0,1.0,1.0,1.0),normal: Vector::zero()},
-        Vertex{pos:Vector::point(1.0,-1.0,1.0),uv: Vector::vec2(0.0,0.0),color: Vector::new(1.0,1.0,1.0,1.0),normal: Vector::zero()},
-        Vertex{pos:Vector::point(1.0,1.0,1.0),uv: Vector::vec2(0.0,1.0),color: Vector::new(1.0,1.0,1.0,1.0),normal: Vector::zero()},
-        Vertex{pos:Vector::point(-1.0,1.0,1.0),uv: Vector::vec2(1.0,1.0),color: Vector::new(1.0,1.0,1.0,1.0),normal: Vector::zero()},
-        Vertex{pos:Vector::point(-1.0,-1.0,-1.0),uv: Vector::vec2(0.0,0.0),color: Vector::new(1.0,1.0,1.0,1.0),normal: Vector::zero()},
-        Vertex{pos:Vector::point(-1.0,1.0,-1.0),uv: Vector::vec2(0.0,1.0),color: Vector::new(1.0,1.0,1.0,1.0),normal: Vector::zero()},
-        Vertex{pos:Vector::point(1.0,1.0,-1.0),uv: Vector::vec2(1.0,1.0),color: Vector::new(1.0,1.0,1.0,1.0),normal: Vector::zero()},
-        Vertex{pos:Vector::point(1.0,-1.0,-1.0),uv: Vector::vec2(1.0,0.0),color: Vector::new(1.0,1.0,1.0,1.0),normal: Vector::zero()},
-    ];
-
-    let indices:&[usize] = &[
-        0, 1, 2, 0, 2, 3, // Quad 0
-        4, 5, 6, 4, 6, 7, // Quad 1
-        5, 3, 2, 5, 2, 6, // Quad 2
-        4, 7, 1, 4, 1, 0, // Quad 3
-        7, 6, 2, 7, 2, 1, // Quad 4
-        4, 0, 3, 4, 3, 5  // Quad 5
-    ];
-
-    let mut ren = Renderer::new(w, h);
+    let (data, indices) = scene::cube();
+
+    let mut ren = ShaderRenderer::new(w, h);
 
     //SDL2
     let sdl_context = sdl2::init()?;
@@ -56,6 +21,7 @@ fn main() -> Result<(), String> {
 
     let window = video_subsystem.window("Soft3D", w as u32, h as u32)
         .position_centered()
+        .resizable()
         .opengl()
         .build()
         .map_err(|e| e.to_string())?;
@@ -70,7 +36,6 @@ fn main() -> Result<(), String> {
     let mut event_pump = sdl_context.event_pump()?;
     let mut x = 0f32;
 
-    ren.set_fs(fs);
     ren.clear_color(0.5,0.8,1.0);
 
     'running: loop {
@@ -80,40 +45,32 @@ fn main() -> Result<(), String> {
                 | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
                     break 'running;
                 }
+                //窗口大小改变, 重建帧缓冲和纹理
+                Event::Window { win_event: WindowEvent::Resized(nw, nh), .. } if nw > 0 && nh > 0 => {
+                    (w, h) = (nw as usize, nh as usize);
+                    ren.resize(w, h);
+                    texture = texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, w as u32, h as u32)
+                        .map_err(|e| e.to_string())?;
+                }
                 _ => {}
             }
         }
         x += 0.1f32;
 
-        let p = Matrix::perspective(f32::consts::PI * 0.5f32, w as f32 / h as f32, 0.1, 1000.0);
-        let view = Matrix::look_at(
-           &Vector::point(-1f32 * f32::sin(x), 1f32, 2f32 * f32::cos(x)),
-            &Vector::point(0.0,0.0,0.0),
-            &Vector::vec(0f32, 1f32, 0f32));
-        ren.set_vs(move |v: &Vertex| -> VSOutput<Vertex>{
-            //let pos = cgmath::Point3::new(v.pos.x,v.pos.y,v.pos.z)*2f32;
-            //let pos = (cp * cview).transform_point(pos);
-            //let pos = Vector::point(pos.x,pos.y,pos.z);
-            VSOutput::new(
-                (&p * &view).apply(&v.pos),
-                //pos,
-                Vertex {
-                    pos: v.pos.clone(),
-                    color: v.color.clone(),
-                    normal: Vector::zero(),
-                    uv: v.uv.clone(),
-                })
-        });
+        let pv = scene::orbit_camera(x, w as f32 / h as f32);
 
         let sy_time = SystemTime::now();
         ren.clear();
-        ren.render_with_index(data.as_slice(),indices);
+        ren.draw_with_index_parallel(&shader, &pv, data.as_slice(),indices.as_slice());
         let d = SystemTime::now().duration_since(sy_time).unwrap().as_millis();
-        canvas.window_mut().set_title(format!("Soft3D {} ms/frame", d).as_ref());
+        canvas.window_mut().set_title(format!("Soft3D {} ms/frame", d).as_ref())
+            .map_err(|e| e.to_string())?;
 
+        let mut result = Ok(());
         ren.get_color_buffer(|buf| {
-            texture.update(None, buf, 3 * w);
+            result = texture.update(None, buf, 3 * w);
         });
+        result.map_err(|e| e.to_string())?;
         canvas.clear();
         canvas.copy(&texture, None, None)?;
         canvas.present();
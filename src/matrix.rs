@@ -34,7 +34,7 @@ impl IndexMut<usize> for Matrix{
     }
 }
 
-impl<'a,'b> Mul<&'a Matrix> for & 'b Matrix{
+impl<'a> Mul<&'a Matrix> for & Matrix{
     type Output = Matrix;
 
     #[inline]
@@ -85,7 +85,7 @@ impl Matrix{
     #[inline]
     pub fn look_at(eye:&Vector,target:&Vector,up:&Vector)->Matrix{
         let zaxis = (target-eye).normalize();
-        let xaxis = zaxis.cross(&up).normalize();
+        let xaxis = zaxis.cross(up).normalize();
         let yaxis = xaxis.cross(&zaxis).normalize();
         let px = xaxis.dot(eye);
         let py = yaxis.dot(eye);
@@ -101,7 +101,43 @@ impl Matrix{
         }
     }
 
+    //高斯-约当消元, 不可逆时返回None
+    pub fn inverse(&self)->Option<Matrix>{
+        let row = |v:&Vector| [v.x,v.y,v.z,v.w];
+        let mut a = [row(&self[0]),row(&self[1]),row(&self[2]),row(&self[3])];
+        let mut inv = [[1f32,0.0,0.0,0.0],[0.0,1.0,0.0,0.0],[0.0,0.0,1.0,0.0],[0.0,0.0,0.0,1.0]];
+        //奇异的阈值随矩阵的大小缩放
+        let tolerance = f32::EPSILON * a.iter().flatten().fold(0f32, |m, x| m.max(x.abs()));
+
+        for c in 0..4 {
+            let pivot = (c..4).max_by(|&i,&j| a[i][c].abs().total_cmp(&a[j][c].abs())).unwrap();
+            if a[pivot][c].abs() <= tolerance {
+                return None;
+            }
+            a.swap(c,pivot);
+            inv.swap(c,pivot);
+
+            let p = 1f32 / a[c][c];
+            for k in 0..4 {
+                a[c][k] *= p;
+                inv[c][k] *= p;
+            }
+            for r in 0..4 {
+                if r != c {
+                    let f = a[r][c];
+                    for k in 0..4 {
+                        a[r][k] -= f * a[c][k];
+                        inv[r][k] -= f * inv[c][k];
+                    }
+                }
+            }
+        }
 
+        let v = |r:[f32;4]| Vector::new(r[0],r[1],r[2],r[3]);
+        Some(Matrix{
+            m:[v(inv[0]),v(inv[1]),v(inv[2]),v(inv[3])]
+        })
+    }
 }
 
 #[cfg(test)]
@@ -155,4 +191,50 @@ mod test{
 
         assert_eq!(c,&a*&b);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_inverse(){
+        let p = Matrix::perspective(1.2,1.5,0.1,100.0);
+        let v = Matrix::look_at(&Vector::point(1.0,2.0,3.0),&Vector::point(0.0,0.0,0.0),&Vector::vec(0.0,1.0,0.0));
+        let m = &p*&v;
+        let i = &m*&m.inverse().unwrap();
+        for r in 0..4 {
+            let e = [i[r].x,i[r].y,i[r].z,i[r].w];
+            for (c,x) in e.iter().enumerate() {
+                let expected = if r == c { 1f32 } else { 0f32 };
+                assert!((x - expected).abs() < 1e-4);
+            }
+        }
+
+        let singular = Matrix{
+            m:[
+                Vector::new(1.0,2.0,3.0,4.0),
+                Vector::new(2.0,4.0,6.0,8.0),
+                Vector::new(0.0,0.0,1.0,0.0),
+                Vector::new(0.0,0.0,0.0,1.0),
+            ]
+        };
+        assert!(singular.inverse().is_none());
+
+        // The tolerance follows the scale of the matrix
+        let small = Matrix{
+            m:[
+                Vector::new(1e-8,0.0,0.0,0.0),
+                Vector::new(0.0,1e-8,0.0,0.0),
+                Vector::new(0.0,0.0,1e-8,0.0),
+                Vector::new(0.0,0.0,0.0,1e-8),
+            ]
+        };
+        assert_eq!(1e8, small.inverse().unwrap()[2].z);
+        // Singular but for one ulp of 80000
+        let nearly = Matrix{
+            m:[
+                Vector::new(1e4,2e4,3e4,4e4),
+                Vector::new(2e4,4e4,6e4,f32::from_bits(8e4f32.to_bits() + 1)),
+                Vector::new(0.0,0.0,1.0,0.0),
+                Vector::new(0.0,0.0,0.0,1.0),
+            ]
+        };
+        assert!(nearly.inverse().is_none());
+    }
+}
@@ -0,0 +1,491 @@
+use crate::vector::Vector;
+use crate::vertex::VertexAttribute;
+
+// Screen coordinates are snapped to 1/256 pixel
+pub(crate) const SUBPIXEL_BITS: u32 = 8;
+const SUBPIXEL_ONE: i64 = 1 << SUBPIXEL_BITS;
+const SUBPIXEL_HALF: i64 = SUBPIXEL_ONE / 2;
+
+#[inline]
+fn to_fixed(v: f32) -> i64 {
+    (v * SUBPIXEL_ONE as f32).round() as i64
+}
+
+// Half-space function of the directed edge a -> b:
+// e(p) = (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+struct Edge {
+    dx: i64,
+    dy: i64,
+    // Value at the first pixel center and its steps for one pixel in x and in y
+    start: i64,
+    step_x: i64,
+    step_y: i64,
+    // 0 for top and left edges, -1 otherwise, so samples exactly on the
+    // edge belong to one triangle only
+    bias: i64,
+}
+
+impl Edge {
+    fn new(a: (i64, i64), b: (i64, i64), origin: (i64, i64)) -> Self {
+        let dx = b.0 - a.0;
+        let dy = b.1 - a.1;
+
+        // The triangle is wound so that its inside is positive, with y
+        // pointing down that makes top edges go right and left edges go up
+        let top_left = (dy == 0 && dx > 0) || dy < 0;
+
+        Edge {
+            dx,
+            dy,
+            start: dx * (origin.1 - a.1) - dy * (origin.0 - a.0),
+            step_x: -dy * SUBPIXEL_ONE,
+            step_y: dx * SUBPIXEL_ONE,
+            bias: if top_left { 0 } else { -1 },
+        }
+    }
+}
+
+// Pixels of a 2x2 quad, index i is pixel:
+// 0 (x, y), 1 (x + 1, y), 2 (x, y + 1), 3 (x + 1, y + 1)
+pub(crate) const QUAD: [(usize, usize); 4] = [(0, 0), (1, 0), (0, 1), (1, 1)];
+
+// Sample positions relative to the pixel center in 1/16 pixel, the standard
+// D3D patterns. One sample sits on the center.
+pub(crate) fn sample_pattern(samples: usize) -> &'static [(i8, i8)] {
+    match samples {
+        1 => &[(0, 0)],
+        2 => &[(4, 4), (-4, -4)],
+        4 => &[(-2, -6), (6, -2), (-6, 2), (2, 6)],
+        8 => &[(1, -3), (-1, 3), (5, 1), (-3, -5), (-5, 5), (-7, -1), (3, 7), (7, -7)],
+        _ => panic!("unsupported sample count {}", samples),
+    }
+}
+
+// Calls `f(x, y, coverage, barycentric)` for every 2x2 quad at even (x, y)
+// with at least one pixel of the rect [x0, x1) x [y0, y1) that has a sample
+// of `pattern` inside the triangle (screen space, y down). Bit s of
+// coverage[i] is set when sample s of pixel i is covered. The barycentric
+// weights are taken at the pixel centers, extrapolated for pixels outside
+// the triangle so the quad can be used for derivatives. The weights follow
+// the order of `p`, either winding is accepted.
+pub(crate) fn rasterize_triangle<F>(p: [&Vector; 3], rect: (usize, usize, usize, usize), pattern: &[(i8, i8)], mut f: F)
+    where F: FnMut(usize, usize, [u8; 4], [[f32; 3]; 4])
+{
+    let mut v = [
+        (to_fixed(p[0].x), to_fixed(p[0].y)),
+        (to_fixed(p[1].x), to_fixed(p[1].y)),
+        (to_fixed(p[2].x), to_fixed(p[2].y)),
+    ];
+    let mut order = [0, 1, 2];
+
+    let mut area = (v[1].0 - v[0].0) * (v[2].1 - v[0].1) - (v[1].1 - v[0].1) * (v[2].0 - v[0].0);
+    if area == 0 {
+        return;
+    }
+    if area < 0 {
+        v.swap(1, 2);
+        order.swap(1, 2);
+        area = -area;
+    }
+
+    //包围盒, 取覆盖到的采样点
+    let min_x = v.iter().map(|v| v.0).min().unwrap();
+    let max_x = v.iter().map(|v| v.0).max().unwrap();
+    let min_y = v.iter().map(|v| v.1).min().unwrap();
+    let max_y = v.iter().map(|v| v.1).max().unwrap();
+
+    let (rx0, ry0, rx1, ry1) = rect;
+    let samples: Vec<(i64, i64)> = pattern.iter().map(|&(x, y)| (x as i64 * SUBPIXEL_ONE / 16, y as i64 * SUBPIXEL_ONE / 16)).collect();
+    let reach = samples.iter().map(|s| s.0.abs().max(s.1.abs())).max().unwrap_or(0);
+    let first_pixel = |min: i64| ((min - SUBPIXEL_HALF - reach + SUBPIXEL_ONE - 1) >> SUBPIXEL_BITS).max(0);
+    let last_pixel = |max: i64| (max - SUBPIXEL_HALF + reach) >> SUBPIXEL_BITS;
+    let x_start = first_pixel(min_x).max(rx0 as i64);
+    let y_start = first_pixel(min_y).max(ry0 as i64);
+    let x_end = (last_pixel(max_x) + 1).min(rx1 as i64);
+    let y_end = (last_pixel(max_y) + 1).min(ry1 as i64);
+    if x_start >= x_end || y_start >= y_end {
+        return;
+    }
+
+    //从偶数坐标开始, 按2x2 quad遍历
+    let (qx_start, qy_start) = (x_start & !1, y_start & !1);
+    let origin = (qx_start * SUBPIXEL_ONE + SUBPIXEL_HALF, qy_start * SUBPIXEL_ONE + SUBPIXEL_HALF);
+    // edges[i] is opposite to v[i], so its value is the weight of v[i]
+    let edges = [
+        Edge::new(v[1], v[2], origin),
+        Edge::new(v[2], v[0], origin),
+        Edge::new(v[0], v[1], origin),
+    ];
+
+    let inv_area = 1f32 / area as f32;
+    let mut row = [edges[0].start, edges[1].start, edges[2].start];
+
+    for y in (qy_start..y_end).step_by(2) {
+        let mut e = row;
+        for x in (qx_start..x_end).step_by(2) {
+            let mut coverage = [0u8; 4];
+            let mut b = [[0f32; 3]; 4];
+            for (i, &(dx, dy)) in QUAD.iter().enumerate() {
+                let (px, py) = (x + dx as i64, y + dy as i64);
+                let mut ej = [0i64; 3];
+                for j in 0..3 {
+                    ej[j] = e[j] + edges[j].step_x * dx as i64 + edges[j].step_y * dy as i64;
+                    b[i][order[j]] = ej[j] as f32 * inv_area;
+                }
+                if px < x_start || px >= x_end || py < y_start || py >= y_end {
+                    continue;
+                }
+                for (s, &(sx, sy)) in samples.iter().enumerate() {
+                    let inside = (0..3).all(|j| ej[j] - edges[j].dy * sx + edges[j].dx * sy + edges[j].bias >= 0);
+                    if inside {
+                        coverage[i] |= 1 << s;
+                    }
+                }
+            }
+            if coverage != [0u8; 4] {
+                f(x as usize, y as usize, coverage, b);
+            }
+            for i in 0..3 {
+                e[i] += edges[i].step_x * 2;
+            }
+        }
+        for i in 0..3 {
+            row[i] += edges[i].step_y * 2;
+        }
+    }
+}
+
+// Calls `f(x, y, [1 - t, t])` for the pixels of a one pixel wide line from
+// p[0] to p[1] inside the rect, t is where the pixel center projects on the
+// line. One pixel per column, or per row when the line is steeper than 45°,
+// from the first endpoint up to but excluding the last, so the segments of a
+// strip don't draw their shared vertices twice.
+pub(crate) fn rasterize_line<F>(p: [&Vector; 2], rect: (usize, usize, usize, usize), mut f: F)
+    where F: FnMut(usize, usize, [f32; 2])
+{
+    let (dx, dy) = (p[1].x - p[0].x, p[1].y - p[0].y);
+    let x_major = dx.abs() >= dy.abs();
+    //a为主轴, b为副轴
+    let (a0, da, b0, db) = if x_major { (p[0].x, dx, p[0].y, dy) } else { (p[0].y, dy, p[0].x, dx) };
+    let (ra0, ra1, rb0, rb1) = if x_major { (rect.0, rect.2, rect.1, rect.3) } else { (rect.1, rect.3, rect.0, rect.2) };
+    if da == 0f32 {
+        return;
+    }
+
+    //像素中心 c + 0.5 在 [a0, a0 + da) 内, da < 0 时为 (a0 + da, a0]
+    let (start, end) = if da > 0f32 {
+        ((a0 - 0.5f32).ceil(), (a0 + da - 0.5f32).ceil())
+    } else {
+        ((a0 + da - 0.5f32).floor() + 1f32, (a0 - 0.5f32).floor() + 1f32)
+    };
+    let start = (start.max(0f32) as usize).max(ra0);
+    let end = (end.max(0f32) as usize).min(ra1);
+
+    for a in start..end {
+        let t = (a as f32 + 0.5f32 - a0) / da;
+        let b = (b0 + t * db).floor();
+        if b < rb0 as f32 || b >= rb1 as f32 {
+            continue;
+        }
+        let b = b as usize;
+        let (x, y) = if x_major { (a, b) } else { (b, a) };
+        f(x, y, [1f32 - t, t]);
+    }
+}
+
+// A point covers the pixel it lies in
+pub(crate) fn rasterize_point<F>(p: &Vector, rect: (usize, usize, usize, usize), mut f: F)
+    where F: FnMut(usize, usize)
+{
+    let (x, y) = (p.x.floor(), p.y.floor());
+    if x >= rect.0 as f32 && x < rect.2 as f32 && y >= rect.1 as f32 && y < rect.3 as f32 {
+        f(x as usize, y as usize);
+    }
+}
+
+// A clipped primitive ready for rasterization: x and y in pixels, z, and
+// 1 / w in w. The attributes are already multiplied by 1 / w, and so are
+// `bary`, the barycentric coordinates of each vertex in the primitive before
+// clipping. `id` is the index of that primitive in the draw call. Points and
+// lines are always front facing.
+pub(crate) enum Primitive<V> {
+    Point { pos: Vector, va: V, id: usize },
+    Line { pos: [Vector; 2], va: [V; 2], bary: [Vector; 2], id: usize },
+    Triangle { pos: [Vector; 3], va: [V; 3], bary: [Vector; 3], front_facing: bool, id: usize },
+}
+
+impl<V> Primitive<V> {
+    pub fn id(&self) -> usize {
+        match self {
+            Primitive::Point { id, .. } | Primitive::Line { id, .. } | Primitive::Triangle { id, .. } => *id,
+        }
+    }
+
+    fn positions(&self) -> &[Vector] {
+        match self {
+            Primitive::Point { pos, .. } => std::slice::from_ref(pos),
+            Primitive::Line { pos, .. } => pos,
+            Primitive::Triangle { pos, .. } => pos,
+        }
+    }
+
+    pub fn front_facing(&self) -> bool {
+        match self {
+            Primitive::Triangle { front_facing, .. } => *front_facing,
+            _ => true,
+        }
+    }
+
+    // Pixels the primitive may cover, (x0, y0, x1, y1) with exclusive end
+    pub fn bounds(&self, width: usize, height: usize) -> (usize, usize, usize, usize) {
+        let p = self.positions();
+        let min = |c: fn(&Vector) -> f32| p.iter().map(c).fold(f32::INFINITY, f32::min).floor().max(0f32) as usize;
+        let max = |c: fn(&Vector) -> f32, limit: usize| ((p.iter().map(c).fold(-f32::INFINITY, f32::max).floor() + 1f32).max(0f32) as usize).min(limit);
+        (min(|v| v.x), min(|v| v.y), max(|v| v.x, width), max(|v| v.y, height))
+    }
+}
+
+// b - a, built from lerp as VertexAttribute has no sub:
+// -2a + (2b + 2a) * 0.5
+pub(crate) fn difference<V: VertexAttribute>(a: &V, b: &V) -> V {
+    V::lerp(&a.scale(-2f32), &b.scale(2f32), 0.5f32)
+}
+
+// Σ b[i] * va[i] with Σ b[i] = 1, built from lerp as VertexAttribute has no add:
+// lerp(lerp(va[p], va[q], b[q] / s), va[r], b[r]) with s = b[p] + b[q] = 1 - b[r].
+// r is picked with |1 - b[r]| largest, the three of them sum to 2 so |s| >= 2/3
+// and nothing is lost to a tiny s, also outside the triangle where b[p] ≈ -b[q].
+pub(crate) fn interpolate<V: VertexAttribute>(va: [&V; 3], b: [f32; 3]) -> V {
+    let r = (0..3).max_by(|&i, &j| (1f32 - b[i]).abs().total_cmp(&(1f32 - b[j]).abs())).unwrap();
+    let (p, q) = ((r + 1) % 3, (r + 2) % 3);
+    let vpq = V::lerp(va[p], va[q], b[q] / (b[p] + b[q]));
+    V::lerp(&vpq, va[r], b[r])
+}
+
+#[cfg(test)]
+mod test {
+    use crate::raster::{rasterize_triangle, rasterize_line, rasterize_point, sample_pattern, interpolate, QUAD};
+    use crate::vector::Vector;
+
+    const W: usize = 16;
+    const H: usize = 16;
+
+    const CENTER: &[(i8, i8)] = &[(0, 0)];
+
+    fn pixels(x: usize, y: usize, coverage: [u8; 4]) -> Vec<(usize, usize)> {
+        QUAD.iter().enumerate().filter(|(i, _)| coverage[*i] != 0).map(|(_, &(dx, dy))| (x + dx, y + dy)).collect()
+    }
+
+    fn coverage(points: &[Vector], indices: &[usize]) -> Vec<u32> {
+        let mut count = vec![0u32; W * H];
+        for t in indices.chunks(3) {
+            let p = [&points[t[0]], &points[t[1]], &points[t[2]]];
+            rasterize_triangle(p, (0, 0, W, H), CENTER, |x, y, mask, _| {
+                for (x, y) in pixels(x, y, mask) {
+                    count[y * W + x] += 1;
+                }
+            });
+        }
+        count
+    }
+
+    // 3x3 grid of quads over the whole rect, inner vertices on pixel
+    // centers, pixel corners and arbitrary sub-pixel positions
+    fn grid() -> (Vec<Vector>, Vec<usize>) {
+        let xs = [0.0, 5.5, 8.0, W as f32];
+        let ys = [0.0, 4.0, 10.5, H as f32];
+        let mut points = vec![];
+        for (j, y) in ys.iter().enumerate() {
+            for (i, x) in xs.iter().enumerate() {
+                let jitter = if i % 3 != 0 && j % 3 != 0 { 0.3 * (i + j) as f32 } else { 0.0 };
+                points.push(Vector::point(x + jitter, y - jitter, 0.0));
+            }
+        }
+        let mut indices = vec![];
+        for j in 0..3 {
+            for i in 0..3 {
+                let a = j * 4 + i;
+                indices.extend_from_slice(&[a, a + 1, a + 5, a, a + 5, a + 4]);
+            }
+        }
+        (points, indices)
+    }
+
+    #[test]
+    fn shared_edges_cover_once() {
+        let (points, indices) = grid();
+        assert!(coverage(&points, &indices).iter().all(|&c| c == 1));
+    }
+
+    #[test]
+    fn fan_covers_once() {
+        // Center on a pixel corner, every edge passes through pixel centers
+        let mut points = vec![Vector::point(8.0, 8.0, 0.0)];
+        let rim = [(0.5, 0.5), (8.5, 0.0), (16.0, 0.5), (16.0, 8.5), (15.5, 16.0), (8.0, 16.0), (0.0, 15.5), (0.0, 8.0)];
+        points.extend(rim.iter().map(|&(x, y)| Vector::point(x, y, 0.0)));
+        let indices: Vec<usize> = (0..rim.len()).flat_map(|i| vec![0, i + 1, (i + 1) % rim.len() + 1]).collect();
+        let count = coverage(&points, &indices);
+        assert!(count.iter().all(|&c| c <= 1));
+        assert_eq!(1, count[8 * W + 8]);
+    }
+
+    #[test]
+    fn top_left_rule() {
+        // Pixel centers on the top and left edges are in, on the bottom
+        // and right edges they are out
+        let p = [Vector::point(0.5, 0.5, 0.0), Vector::point(4.5, 0.5, 0.0), Vector::point(0.5, 4.5, 0.0)];
+        let mut pixels = vec![];
+        rasterize_triangle([&p[0], &p[1], &p[2]], (0, 0, W, H), CENTER, |x, y, mask, _| pixels.extend(self::pixels(x, y, mask)));
+        assert!(pixels.contains(&(0, 0)));
+        assert!(pixels.contains(&(3, 0)));
+        assert!(pixels.contains(&(0, 3)));
+        assert!(!pixels.contains(&(4, 0)));
+        assert!(!pixels.contains(&(0, 4)));
+        assert!(!pixels.contains(&(2, 2)));
+    }
+
+    #[test]
+    fn negative_coordinates() {
+        let p = [Vector::point(-10.0, -10.0, 0.0), Vector::point(20.0, -10.0, 0.0), Vector::point(-10.0, 20.0, 0.0)];
+        let mut pixels = vec![];
+        rasterize_triangle([&p[0], &p[1], &p[2]], (0, 0, W, H), CENTER, |x, y, mask, b| {
+            assert!(b.iter().all(|b| (b[0] + b[1] + b[2] - 1.0).abs() < 1e-5));
+            pixels.extend(self::pixels(x, y, mask));
+        });
+        // x + y = 10 is the diagonal through the pixel centers, exclusive
+        assert_eq!(pixels.len(), (0..10).sum::<usize>());
+        assert!(pixels.iter().all(|&(x, y)| x + y < 9));
+    }
+
+    fn line(a: (f32, f32), b: (f32, f32)) -> Vec<(usize, usize)> {
+        let p = [Vector::point(a.0, a.1, 0.0), Vector::point(b.0, b.1, 0.0)];
+        let mut pixels = vec![];
+        rasterize_line([&p[0], &p[1]], (0, 0, W, H), |x, y, b| {
+            assert!((b[0] + b[1] - 1.0).abs() < 1e-5);
+            pixels.push((x, y));
+        });
+        pixels
+    }
+
+    #[test]
+    fn line_one_pixel_per_column() {
+        let pixels = line((0.5, 1.5), (8.5, 5.5));
+        assert_eq!(pixels.len(), 8);
+        assert_eq!(pixels[0], (0, 1));
+        assert!(pixels.windows(2).all(|p| p[1].0 == p[0].0 + 1 && p[1].1 >= p[0].1));
+        // Steep lines step in y, the first endpoint is in and the last out
+        let pixels = line((2.5, 12.5), (4.5, 0.5));
+        assert_eq!(pixels.len(), 12);
+        assert!(pixels.windows(2).all(|p| p[1].1 == p[0].1 + 1));
+        assert_eq!((4, 1), pixels[0]);
+        assert_eq!((2, 12), pixels[11]);
+    }
+
+    #[test]
+    fn line_strip_covers_once() {
+        let strip = [(0.5, 0.5), (10.5, 3.5), (3.5, 14.5), (0.5, 0.5)];
+        let mut count = vec![0u32; W * H];
+        for s in strip.windows(2) {
+            for (x, y) in line(s[0], s[1]) {
+                count[y * W + x] += 1;
+            }
+        }
+        assert!(count.iter().all(|&c| c <= 1));
+        assert_eq!(1, count[0]);
+    }
+
+    #[test]
+    fn line_clipped_to_rect() {
+        let p = [Vector::point(-4.0, 2.5, 0.0), Vector::point(20.0, 2.5, 0.0)];
+        let mut pixels = vec![];
+        rasterize_line([&p[0], &p[1]], (4, 0, 8, H), |x, y, b| pixels.push((x, y, b[1])));
+        assert_eq!(pixels.iter().map(|p| (p.0, p.1)).collect::<Vec<_>>(), vec![(4, 2), (5, 2), (6, 2), (7, 2)]);
+        assert!((pixels[0].2 - 8.5 / 24.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn point_covers_its_pixel() {
+        let mut pixels = vec![];
+        for p in &[Vector::point(3.0, 4.99, 0.0), Vector::point(-0.5, 2.0, 0.0), Vector::point(15.9, 0.0, 0.0)] {
+            rasterize_point(p, (0, 0, W, H), |x, y| pixels.push((x, y)));
+        }
+        assert_eq!(pixels, vec![(3, 4), (15, 0)]);
+    }
+
+    #[test]
+    fn quads_are_aligned() {
+        // Rect and triangle both start on odd pixels, the quads still start
+        // on even ones and mask out what is outside the rect
+        let p = [Vector::point(3.0, 3.0, 0.0), Vector::point(12.0, 3.0, 0.0), Vector::point(3.0, 12.0, 0.0)];
+        let mut quads = vec![];
+        rasterize_triangle([&p[0], &p[1], &p[2]], (5, 5, 9, 9), CENTER, |x, y, mask, b| {
+            assert!(x % 2 == 0 && y % 2 == 0);
+            // Weights step linearly inside the quad, uncovered pixels included
+            assert!((0..3).all(|j| (b[1][j] - b[0][j] - (b[3][j] - b[2][j])).abs() < 1e-5));
+            quads.push((x, y, mask));
+        });
+        assert_eq!((4, 4, [0, 0, 0, 1]), quads[0]);
+        let covered: usize = quads.iter().map(|q| pixels(q.0, q.1, q.2).len()).sum();
+        // Pixels of [5, 9)^2 whose center is below the hypotenuse x + y = 15
+        assert_eq!(covered, (5..9).flat_map(|x| (5..9).map(move |y| (x, y))).filter(|(x, y)| x + y < 14).count());
+    }
+
+    // Coverage count of every sample of every pixel
+    fn sample_coverage(points: &[Vector], indices: &[usize], samples: usize) -> Vec<u32> {
+        let mut count = vec![0u32; W * H * samples];
+        for t in indices.chunks(3) {
+            let p = [&points[t[0]], &points[t[1]], &points[t[2]]];
+            rasterize_triangle(p, (0, 0, W, H), sample_pattern(samples), |x, y, coverage, _| {
+                for (i, &(dx, dy)) in QUAD.iter().enumerate() {
+                    for s in (0..samples).filter(|s| coverage[i] & (1 << s) != 0) {
+                        count[((y + dy) * W + x + dx) * samples + s] += 1;
+                    }
+                }
+            });
+        }
+        count
+    }
+
+    #[test]
+    fn samples_cover_once() {
+        let (points, indices) = grid();
+        for &n in &[2, 4, 8] {
+            assert!(sample_coverage(&points, &indices, n).iter().all(|&c| c == 1));
+        }
+    }
+
+    #[test]
+    fn partial_coverage() {
+        // Vertical edge at x = 4.5 through the pixel centers of column 4,
+        // 4x samples at x offsets -6, 6, -2 and 2 sixteenths
+        let p = [Vector::point(0.0, 0.0, 0.0), Vector::point(4.5, 0.0, 0.0), Vector::point(4.5, 8.0, 0.0)];
+        let count = sample_coverage(&p, &[0, 1, 2], 4);
+        let pixel = |x: usize, y: usize| &count[(y * W + x) * 4..(y * W + x + 1) * 4];
+        assert_eq!(&[1, 0, 1, 0], pixel(4, 4));
+        assert_eq!(&[0, 0, 0, 0], pixel(5, 4));
+        // The other edge is the diagonal y = 16 / 9 * x, pixel (0, 1) has
+        // its center outside but sample 1 at (0.875, 1.375) inside
+        assert_eq!(&[1, 1, 0, 1], pixel(0, 0));
+        assert_eq!(&[0, 1, 0, 0], pixel(0, 1));
+    }
+
+    // The helper pixels (0, 1) and (1, 1) of the quad of pixel (0, 0) lie on
+    // b2 = 1 with b0 = -b1, interpolating there still follows the plane of
+    // the attribute
+    #[test]
+    fn interpolate_helper_pixels() {
+        let p = [Vector::point(0.25, 0.25, 0.0), Vector::point(1.25, 0.25, 0.0), Vector::point(0.25, 1.5, 0.0)];
+        let f = |x: f32, y: f32| 100.0 * x - 40.0 * y + 7.0;
+        let va = p.each_ref().map(|p| f(p.x, p.y));
+        let mut quads = 0;
+        rasterize_triangle([&p[0], &p[1], &p[2]], (0, 0, W, H), sample_pattern(1), |x, y, _, b| {
+            for (i, &(dx, dy)) in QUAD.iter().enumerate() {
+                let expected = f((x + dx) as f32 + 0.5, (y + dy) as f32 + 0.5);
+                let v = interpolate([&va[0], &va[1], &va[2]], b[i]);
+                assert!((v - expected).abs() < 1e-3, "{:?}: {} instead of {}", b[i], v, expected);
+            }
+            quads += 1;
+        });
+        assert_eq!(1, quads);
+    }
+}
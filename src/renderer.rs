@@ -1,9 +1,21 @@
 use std::cell::RefCell;
-use std::mem::swap;
-use std::f32::INFINITY;
 use crate::vector::Vector;
+use crate::matrix::Matrix;
+use crate::cubemap::CubeTexture;
+use crate::sampler::Sampler;
 use crate::vertex::VertexAttribute;
+use crate::raster::{self, Primitive};
+use crate::tile::{self, Tile};
+use crate::framebuffer::{Framebuffer, MAX_COLOR_ATTACHMENTS};
+use crate::format::ColorFormat;
+use crate::downsample::{DownsampleFilter, DownsampleBuffers};
+use crate::state::{PipelineState, BlendState, DepthState, StencilState, RasterizerState, MultisampleState, Viewport, FrontFace};
+use crate::topology::PrimitiveTopology;
+use crate::shader::{Shader, Closures};
+use std::thread;
 use std::marker::PhantomData;
+use std::path::Path;
+use image::{ImageBuffer, RgbImage, RgbaImage, GrayImage, ImageResult};
 
 pub struct VSOutput<VA: VertexAttribute>{
     pub pos:Vector,
@@ -18,39 +30,93 @@ impl<VA: VertexAttribute> VSOutput<VA>{
     }
 }
 
-struct Segment<'a, V: VertexAttribute> {
-    pub s: (&'a Vector, &'a V),
-    pub e: (&'a Vector, &'a V),
+// What the fragment shader sees of a fragment
+pub struct FragmentInput<'a, VA: VertexAttribute>{
+    // Interpolated vertex attributes
+    pub va:&'a VA,
+    // Change of the attributes to the next pixel in x and in y (dFdx, dFdy),
+    // from the 2x2 quad the fragment was shaded in. Zero for points and lines.
+    pub ddx:&'a VA,
+    pub ddy:&'a VA,
+    // The fragment belongs to a front face, see RasterizerState::front_face
+    pub front_facing:bool,
+    // Pixel center in x and y, in pixels of the framebuffer drawn to, depth
+    // after the depth range in z and 1 / w of the clip position in w
+    pub frag_coord:Vector,
+    // Index of the primitive in the draw call, pieces cut off by clipping
+    // keep it
+    pub primitive_id:usize,
+    // Weights of the primitive's vertices in x, y, z, perspective correct
+    // and relative to the unclipped primitive. (1 - t, t, 0) on lines.
+    pub barycentrics:Vector,
 }
 
-impl<'a, V: VertexAttribute> Segment<'a, V> {
-    pub fn new(a: (&'a Vector, &'a V), b: (&'a Vector, &'a V)) -> Self {
-        if a.0.y < b.0.y {
-            Self {
-                s: a,
-                e: b,
-            }
-        } else {
-            Self {
-                s: b,
-                e: a,
-            }
-        }
+// What the fragment shader writes, output i goes to color attachment i of
+// the bound framebuffer. Attachments without an output are left as is.
+// A Vector is one output, [Vector; N] N outputs. None or discard() drops
+// the fragment.
+#[derive(Debug, Clone)]
+pub struct FragmentOutput {
+    colors: [Vector; MAX_COLOR_ATTACHMENTS],
+    count: usize,
+    discarded: bool,
+    depth: Option<f32>,
+}
+
+impl FragmentOutput {
+    // Nothing is written for the fragment, not even depth and stencil
+    pub fn discard() -> Self {
+        FragmentOutput { discarded: true, ..FragmentOutput::from([]) }
+    }
+
+    pub fn is_discarded(&self) -> bool {
+        self.discarded
     }
 
-    pub fn length(&self) -> f32 {
-        (self.e.0 - self.s.0).length()
+    // Replaces the interpolated depth of every sample in the depth test,
+    // clamped to [0, 1]. The shader has to declare it with
+    // Shader::writes_depth (Renderer::set_fs_writes_depth for closures),
+    // drawing panics otherwise.
+    pub fn with_depth(mut self, depth: f32) -> Self {
+        self.depth = Some(depth.clamp(0f32, 1f32));
+        self
     }
 
-    pub fn length_y(&self) -> f32 {
-        (self.e.0.y - self.s.0.y).abs()
+    pub fn depth(&self) -> Option<f32> {
+        self.depth
     }
 
-    pub fn length_x(&self) -> f32 {
-        (self.e.0.x - self.s.0.x).abs()
+    pub fn colors(&self) -> &[Vector] {
+        &self.colors[..self.count]
+    }
+}
+
+impl From<Vector> for FragmentOutput {
+    fn from(color: Vector) -> Self {
+        FragmentOutput::from([color])
+    }
+}
+
+impl<const N: usize> From<[Vector; N]> for FragmentOutput {
+    fn from(outputs: [Vector; N]) -> Self {
+        assert!(N <= MAX_COLOR_ATTACHMENTS, "at most {} fragment outputs", MAX_COLOR_ATTACHMENTS);
+        let mut colors: [Vector; MAX_COLOR_ATTACHMENTS] = std::array::from_fn(|_| Vector::zero());
+        for (i, c) in IntoIterator::into_iter(outputs).enumerate() {
+            colors[i] = c;
+        }
+        FragmentOutput { colors, count: N, discarded: false, depth: None }
     }
 }
 
+impl<T: Into<FragmentOutput>> From<Option<T>> for FragmentOutput {
+    fn from(output: Option<T>) -> Self {
+        output.map_or_else(FragmentOutput::discard, Into::into)
+    }
+}
+
+// Barycentric coordinates of the vertices of a triangle
+const CORNERS: [Vector; 3] = [Vector::vec(1f32, 0f32, 0f32), Vector::vec(0f32, 1f32, 0f32), Vector::vec(0f32, 0f32, 1f32)];
+
 #[derive(Clone, Copy)]
 enum Plane {
     NX = 0,
@@ -92,45 +158,105 @@ impl Plane{
     }
 }
 
-// V is Vertex attributes
-pub struct Renderer<VS, FS, V: VertexAttribute> where
+// V is Vertex attributes, O what the fragment shader returns. VS and FS are
+// the closures of set_vs and set_fs used by the render* calls, the draw*
+// calls take a Shader program instead.
+pub struct Renderer<VS, FS, V: VertexAttribute, O = Vector> where
     VS: Fn(&V) -> VSOutput<V>,
-    FS: Fn(&V) -> Vector
+    FS: Fn(&FragmentInput<V>) -> O,
+    O: Into<FragmentOutput>
 {
-    width: usize,
-    height: usize,
-    clear_color:[u8;3],
+    clear_color:Vector,
+    clear_depth:f32,
+    clear_stencil:u8,
+    depth_range:(f32, f32),
+    // (x, y, width, height) in pixels, None for the whole framebuffer
+    viewport: Option<(f32, f32, f32, f32)>,
+    // (x0, y0, x1, y1) in pixels with exclusive end
+    scissor: Option<(usize, usize, usize, usize)>,
 
     vertex_shader: Option<VS>,
     fragment_shader: Option<FS>,
-
-    color_buffer: RefCell<Vec<u8>>,
-    depth_buffer: RefCell<Vec<f32>>,
-    _phantom: PhantomData<V>,
+    fs_writes_depth: bool,
+
+    // The default framebuffer, one color attachment
+    framebuffer: RefCell<Framebuffer>,
+    // Set by bind_framebuffer, draws go here instead of the default framebuffer
+    bound: Option<RefCell<Framebuffer>>,
+    // RGB24 handed out by get_color_buffer and the supersampled color it is
+    // made from, kept so every frame reuses them
+    present: RefCell<Vec<u8>>,
+    downsampled: RefCell<DownsampleBuffers>,
+    // Factor the default framebuffer is larger than the output, and the
+    // filter it is presented with
+    supersampling: (usize, DownsampleFilter),
+    threads: usize,
+    topology: PrimitiveTopology,
+    state: PipelineState,
+    _phantom: PhantomData<(V, O)>,
 }
 
+// A renderer only drawn to with Shader programs (draw, draw_with_index...),
+// spares naming closure types that set_vs and set_fs would otherwise fix
+pub type ShaderRenderer<V> = Renderer<fn(&V) -> VSOutput<V>, fn(&FragmentInput<V>) -> Vector, V>;
 
-impl<VS, FS, V> Renderer<VS, FS, V> where
+impl<VS, FS, V, O> Renderer<VS, FS, V, O> where
     VS: Fn(&V) -> VSOutput<V>,
-    FS: Fn(&V) -> Vector,
-    V: VertexAttribute
+    FS: Fn(&FragmentInput<V>) -> O,
+    V: VertexAttribute,
+    O: Into<FragmentOutput>
 {
     pub fn new(w: usize, h: usize) -> Self {
         Renderer {
-            width: w,
-            height: h,
-            clear_color: [0u8;3],
+            clear_color: Vector::new(0f32, 0f32, 0f32, 1f32),
+            clear_depth: 1f32,
+            clear_stencil: 0u8,
+            depth_range: (0f32, 1f32),
+            viewport: None,
+            scissor: None,
 
             vertex_shader: None,
             fragment_shader: None,
+            fs_writes_depth: false,
 
-            color_buffer: RefCell::new(vec![0u8; w * h * 3]),
-            depth_buffer: RefCell::new(vec![-INFINITY; w * h]),
+            framebuffer: RefCell::new(Framebuffer::new(w, h, 1)),
+            bound: None,
+            present: RefCell::new(Vec::new()),
+            downsampled: RefCell::new(DownsampleBuffers::default()),
+            supersampling: (1, DownsampleFilter::default()),
+            threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            topology: PrimitiveTopology::default(),
+            state: PipelineState::default(),
 
             _phantom: PhantomData {},
         }
     }
 
+    // Output size, what get_color_buffer and color_image return
+    pub fn width(&self) -> usize {
+        self.framebuffer.borrow().width() / self.supersampling.0
+    }
+
+    pub fn height(&self) -> usize {
+        self.framebuffer.borrow().height() / self.supersampling.0
+    }
+
+    // Reallocates the default framebuffer at the new output size, keeping its
+    // format, sample count and supersampling. The content is lost, the
+    // viewport and scissor are kept. A bound framebuffer is not touched.
+    // 0 is allowed, e.g. for a minimized window, draws then do nothing.
+    pub fn resize(&mut self, w: usize, h: usize) {
+        let fb = self.framebuffer.get_mut();
+        let (format, samples) = (fb.format(0), fb.samples());
+        self.reallocate(w, h, format, samples);
+    }
+
+    // New default framebuffer, `w` x `h` is the output size
+    fn reallocate(&mut self, w: usize, h: usize, format: ColorFormat, samples: usize) {
+        let k = self.supersampling.0;
+        *self.framebuffer.get_mut() = Framebuffer::with_formats(w * k, h * k, &[format]).with_samples(samples);
+    }
+
     pub fn set_vs(&mut self,vs:VS){
         self.vertex_shader = Some(vs)
     }
@@ -139,63 +265,434 @@ impl<VS, FS, V> Renderer<VS, FS, V> where
         self.fragment_shader = Some(fs)
     }
 
+    // Whether the set_fs closure may set a depth, like Shader::writes_depth.
+    // Off by default so hidden fragments are rejected before it runs.
+    pub fn set_fs_writes_depth(&mut self, writes_depth: bool) {
+        self.fs_writes_depth = writes_depth;
+    }
+
+    // Worker threads used by render_parallel and render_with_index_parallel
+    pub fn set_threads(&mut self, n: usize) {
+        self.threads = n.max(1);
+    }
+
+    // Used by the following draw calls
+    pub fn set_primitive_topology(&mut self, topology: PrimitiveTopology) {
+        self.topology = topology;
+    }
+
+    pub fn set_blend_state(&mut self, blend: BlendState) {
+        self.state.blend = blend;
+    }
+
+    pub fn set_depth_state(&mut self, depth: DepthState) {
+        self.state.depth = depth;
+    }
+
+    // Maps NDC z from [-1, 1] to [near, far] like glDepthRange, both clamped
+    // to [0, 1]. near > far is allowed, e.g. (1, 0) for reverse-Z.
+    pub fn set_depth_range(&mut self, near: f32, far: f32) {
+        self.depth_range = (near.clamp(0f32, 1f32), far.clamp(0f32, 1f32));
+    }
+
+    // Maps NDC to a rectangle of the framebuffer, y down from the top-left
+    // corner like the color buffer. Primitives are still clipped to NDC, so
+    // nothing is drawn outside the viewport. Also sets the depth range.
+    pub fn set_viewport(&mut self, viewport: Viewport) {
+        self.viewport = Some((viewport.x, viewport.y, viewport.width, viewport.height));
+        self.set_depth_range(viewport.min_depth, viewport.max_depth);
+    }
+
+    // Back to the whole framebuffer, the depth range is kept
+    pub fn reset_viewport(&mut self) {
+        self.viewport = None;
+    }
+
+    // Fragments outside the rectangle are discarded and clear() leaves the
+    // pixels outside it alone
+    pub fn set_scissor(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        self.scissor = Some((x, y, x + width, y + height));
+    }
+
+    pub fn disable_scissor(&mut self) {
+        self.scissor = None;
+    }
+
+    pub fn clear_depth(&mut self, d: f32) {
+        self.clear_depth = d;
+    }
+
+    pub fn set_rasterizer_state(&mut self, rasterizer: RasterizerState) {
+        self.state.rasterizer = rasterizer;
+    }
+
+    pub fn set_stencil_state(&mut self, stencil: StencilState) {
+        self.state.stencil = stencil;
+    }
+
+    pub fn clear_stencil(&mut self, s: u8) {
+        self.clear_stencil = s;
+    }
+
     pub fn clear_color(&mut self,r:f32,g:f32,b:f32){
-        self.clear_color = [(r * 255f32) as u8,(g * 255f32) as u8,(b * 255f32) as u8];
+        self.clear_color_alpha(r, g, b, 1f32);
+    }
+
+    // Rounded to each attachment's format on clear, float formats keep
+    // values outside [0, 1]
+    pub fn clear_color_alpha(&mut self, r: f32, g: f32, b: f32, a: f32) {
+        self.clear_color = Vector::new(r, g, b, a);
     }
 
+    // Replaces the default framebuffer with one of `format`, its content is lost
+    pub fn set_color_format(&mut self, format: ColorFormat) {
+        let samples = self.framebuffer.get_mut().samples();
+        self.reallocate(self.width(), self.height(), format, samples);
+    }
+
+    // MSAA for the default framebuffer, 1, 2, 4 or 8 samples per pixel. Its
+    // content is lost.
+    pub fn set_sample_count(&mut self, samples: usize) {
+        let format = self.framebuffer.get_mut().format(0);
+        self.reallocate(self.width(), self.height(), format, samples);
+    }
+
+    // SSAA: the default framebuffer is rasterized at `factor` times the
+    // output size on both axes and shrunk with `filter` when presented
+    // (get_color_buffer, color_image, depth_image...). 1 turns it off. Its
+    // content is lost.
+    pub fn set_supersampling(&mut self, factor: usize, filter: DownsampleFilter) {
+        let (w, h) = (self.width(), self.height());
+        let fb = self.framebuffer.get_mut();
+        let (format, samples) = (fb.format(0), fb.samples());
+        self.supersampling = (factor.max(1), filter);
+        self.reallocate(w, h, format, samples);
+    }
+
+    pub fn set_multisample_state(&mut self, multisample: MultisampleState) {
+        self.state.multisample = multisample;
+    }
+
+    // Draws and clears go to `fb` until it is unbound
+    pub fn bind_framebuffer(&mut self, fb: Framebuffer) {
+        self.bound = Some(RefCell::new(fb));
+    }
+
+    // Back to the default framebuffer, returns the one that was bound
+    pub fn unbind_framebuffer(&mut self) -> Option<Framebuffer> {
+        self.bound.take().map(RefCell::into_inner)
+    }
+
+    // The framebuffer draws go to
+    fn target(&self) -> &RefCell<Framebuffer> {
+        self.bound.as_ref().unwrap_or(&self.framebuffer)
+    }
+
+    // Pixels of the target per output pixel on each axis, the viewport and
+    // scissor are given in output pixels
+    fn scale(&self) -> usize {
+        if self.bound.is_none() { self.supersampling.0 } else { 1 }
+    }
+
+    fn viewport_rect(&self, fb: &Framebuffer) -> (f32, f32, f32, f32) {
+        let k = self.scale() as f32;
+        match self.viewport {
+            Some((x, y, w, h)) => (x * k, y * k, w * k, h * k),
+            None => (0f32, 0f32, fb.width() as f32, fb.height() as f32),
+        }
+    }
+
+    // The pixels draws and clears may touch, the scissor inside the target
+    fn draw_rect(&self, fb: &Framebuffer) -> (usize, usize, usize, usize) {
+        let (w, h) = (fb.width(), fb.height());
+        let k = self.scale();
+        match self.scissor {
+            Some((x0, y0, x1, y1)) => ((x0 * k).min(w), (y0 * k).min(h), (x1 * k).min(w), (y1 * k).min(h)),
+            None => (0, 0, w, h),
+        }
+    }
+
+    // Calls `f` with the bound framebuffer as it is shown, the default one
+    // is downsampled first when supersampled
+    fn presented<R, F>(&self, f: F) -> R
+        where F: FnOnce(&Framebuffer) -> R
+    {
+        let fb = self.target().borrow();
+        match self.supersampling {
+            (k, filter) if k > 1 && self.bound.is_none() => f(&fb.downsample(k, filter)),
+            _ => f(&fb),
+        }
+    }
+
+    // Calls `f` with color attachment 0 of the bound framebuffer as it is
+    // shown, RGBA f32, its format and size. Only the color is downsampled
+    // when supersampled.
+    fn presented_color<R, F>(&self, f: F) -> R
+        where F: FnOnce(&[f32], ColorFormat, usize, usize) -> R
+    {
+        let fb = self.target().borrow();
+        match self.supersampling {
+            (k, filter) if k > 1 && self.bound.is_none() => {
+                let mut buffers = self.downsampled.borrow_mut();
+                let colors = filter.apply_into(fb.color_buffer(0), fb.width(), fb.height(), k, &mut buffers);
+                f(colors, fb.format(0), fb.width() / k, fb.height() / k)
+            }
+            _ => f(fb.color_buffer(0), fb.format(0), fb.width(), fb.height()),
+        }
+    }
+
+    // Color attachment 0 of the bound framebuffer as RGB24
     pub fn get_color_buffer<F>(&self, mut cb: F)
         where F: FnMut(&[u8])
     {
-        cb(self.color_buffer.borrow_mut().as_slice())
+        let mut bytes = self.present.borrow_mut();
+        self.presented_color(|colors, format, _, _| format.present_rgb(colors, &mut bytes));
+        cb(&bytes)
+    }
+
+    pub fn color_image(&self) -> RgbImage {
+        self.presented_color(|colors, format, w, h| {
+            let mut bytes = Vec::new();
+            format.present_rgb(colors, &mut bytes);
+            ImageBuffer::from_raw(w as u32, h as u32, bytes).unwrap()
+        })
+    }
+
+    pub fn color_image_rgba(&self) -> RgbaImage {
+        self.presented_color(|colors, format, w, h| ImageBuffer::from_raw(w as u32, h as u32, format.present_rgba(colors)).unwrap())
+    }
+
+    //近处为黑,远处和未写入的像素为白
+    pub fn depth_image(&self) -> GrayImage {
+        self.presented(|fb| fb.depth_image(self.clear_depth))
     }
 
+    // Format is picked from the file extension (png, jpg, bmp...)
+    pub fn save_color_buffer<P: AsRef<Path>>(&self, path: P) -> ImageResult<()> {
+        self.color_image().save(path)
+    }
+
+    pub fn save_depth_buffer<P: AsRef<Path>>(&self, path: P) -> ImageResult<()> {
+        self.depth_image().save(path)
+    }
+
+    // Clears every attachment of the bound framebuffer, inside the scissor
+    // when it is set
     pub fn clear(&self) {
-        let mut i = 0;
-        for c in self.color_buffer.borrow_mut().iter_mut() {
-            *c = self.clear_color[i];
-            i = (i + 1) % self.clear_color.len()
-        }
+        let mut fb = self.target().borrow_mut();
+        let rect = self.draw_rect(&fb);
+        fb.clear(rect, &self.clear_color, self.clear_depth, self.clear_stencil);
+    }
 
-        for d in self.depth_buffer.borrow_mut().iter_mut() {
-            *d = INFINITY;
+    // Fills the pixels no geometry has written depth to with the cube texture
+    // seen through `view_projection`, the same matrix the vertex shader uses.
+    // Can be drawn before or after the scene, depth and stencil are left as is.
+    // Only covers the viewport, and the scissor when it is set.
+    pub fn draw_skybox(&self, sky: &CubeTexture, sampler: &Sampler, view_projection: &Matrix) {
+        let inv = match view_projection.inverse() {
+            Some(inv) => inv,
+            None => return,
+        };
+        let unproject = |x: f32, y: f32, z: f32| {
+            let p = inv.apply(&Vector::new(x, y, z, 1f32));
+            p.scale(1f32 / p.w)
+        };
+        let mut fb = self.target().borrow_mut();
+        let (width, samples) = (fb.width(), fb.samples());
+        let (vx, vy, vw, vh) = self.viewport_rect(&fb);
+        let (x0, y0, x1, y1) = self.draw_rect(&fb);
+        //像素中心在视口内
+        let clamp = |v: f32, min: usize, max: usize| ((v - 0.5f32).ceil().max(0f32) as usize).clamp(min, max);
+        let (x0, x1) = (clamp(vx, x0, x1), clamp(vx + vw, x0, x1));
+        let (y0, y1) = (clamp(vy, y0, y1), clamp(vy + vh, y0, y1));
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let i = (y * width + x) * samples;
+                if fb.depth[i..i + samples].iter().all(|&d| d != self.clear_depth) {
+                    continue;
+                }
+                //像素中心反投影到近平面和远平面, 两点之差是视线方向
+                let nx = (x as f32 + 0.5f32 - vx) / vw * 2f32 - 1f32;
+                let ny = 1f32 - (y as f32 + 0.5f32 - vy) / vh * 2f32;
+                let dir = &unproject(nx, ny, 1f32) - &unproject(nx, ny, -1f32);
+                let c = sky.sample(sampler, &dir);
+                for s in 0..samples {
+                    if fb.depth[i + s] == self.clear_depth {
+                        fb.set_sample_color(0, x, y, s, &c);
+                    }
+                }
+            }
         }
+        fb.resolve((x0, y0, x1, y1));
     }
 
     pub fn render(&self, vertices: &[V]) {
-        let mut data:Vec<VSOutput<V>> = vertices.iter().map(|x| {
-            (self.vertex_shader.as_ref().unwrap())(&x)
-        }).collect();
+        self.draw(&self.closures(), &(), vertices);
+    }
+
+    pub fn render_with_index(&self, vertices: &[V], indices: &[usize]) {
+        self.draw_with_index(&self.closures(), &(), vertices, indices);
+    }
+
+    // Like render but with `shader` instead of set_vs and set_fs, `uniforms`
+    // are handed to both of its stages
+    pub fn draw<S>(&self, shader: &S, uniforms: &S::Uniforms, vertices: &[V])
+        where S: Shader<V> + ?Sized
+    {
+        let data: Vec<VSOutput<V>> = vertices.iter().map(|v| shader.vertex(uniforms, v)).collect();
+        let primitives = self.setup_primitives(&data, data.len(), |i| i);
+        self.draw_primitives(&primitives, &|f: &FragmentInput<V>| shader.fragment(uniforms, f).into(), !shader.writes_depth());
+    }
+
+    pub fn draw_with_index<S>(&self, shader: &S, uniforms: &S::Uniforms, vertices: &[V], indices: &[usize])
+        where S: Shader<V> + ?Sized
+    {
+        let data: Vec<VSOutput<V>> = vertices.iter().map(|v| shader.vertex(uniforms, v)).collect();
+        let primitives = self.setup_primitives(&data, indices.len(), |i| indices[i]);
+        self.draw_primitives(&primitives, &|f: &FragmentInput<V>| shader.fragment(uniforms, f).into(), !shader.writes_depth());
+    }
 
-        for i in (0..data.len() / 3).map(|x| x * 3) {
-            let p0 = data.get(i).unwrap();
-            let p1 = data.get(i + 1).unwrap();
-            let p2 = data.get(i + 2).unwrap();
-            self.draw_triangle(p0, p1, p2,true);
+    // set_vs and set_fs as a Shader
+    fn closures(&self) -> Closures<'_, VS, FS, O> {
+        Closures::new(self.vertex_shader.as_ref().unwrap(), self.fragment_shader.as_ref().unwrap(), self.fs_writes_depth)
+    }
+
+    // `index(i)` is the vertex at position i of the stream, `count` its length
+    fn setup_primitives<I>(&self, data: &[VSOutput<V>], count: usize, index: I) -> Vec<Primitive<V>>
+        where I: Fn(usize) -> usize
+    {
+        let mut out = Vec::new();
+        let mut id = 0;
+        self.topology.assemble(count, |p| {
+            match *p {
+                [i0] => self.draw_point(&data[index(i0)], id, &mut out),
+                [i0, i1] => self.draw_line(&data[index(i0)], &data[index(i1)], id, &mut out),
+                [i0, i1, i2] => {
+                    let p = [&data[index(i0)], &data[index(i1)], &data[index(i2)]];
+                    self.draw_triangle(p, CORNERS.each_ref(), &p[2].va, true, id, &mut out)
+                }
+                _ => unreachable!(),
+            }
+            id += 1;
+        });
+        out
+    }
+
+    //单线程: 整个framebuffer作为一个tile
+    // `early_z` lets hidden fragments be rejected before the shader runs, off
+    // when it may write depth
+    fn draw_primitives<F>(&self, primitives: &[Primitive<V>], fs: &F, early_z: bool)
+        where F: Fn(&FragmentInput<V>) -> FragmentOutput
+    {
+        let mut fb = self.target().borrow_mut();
+        //窗口最小化时可能是0x0, 没有像素可画
+        if fb.width() == 0 || fb.height() == 0 {
+            return;
+        }
+        let size = fb.width().max(fb.height());
+        let clip = self.draw_rect(&fb);
+
+        for mut tile in Tile::split(&mut fb, size) {
+            for prim in primitives {
+                Self::rasterize(&mut tile, clip, prim, fs, early_z, &self.state);
+            }
         }
+        let rect = Self::touched_rect(primitives, &fb, clip);
+        fb.resolve(rect);
     }
 
-    pub fn render_with_index(&self, vertices: &[V], indices: &[usize]) {
-        let mut data: Vec<VSOutput<V>> = vertices.iter().map(|x| {
-            (self.vertex_shader.as_ref().unwrap())(&x)
-        }).collect();
+    // Bounds of all `primitives` inside `clip`, the pixels a draw may have
+    // written and has to resolve
+    fn touched_rect(primitives: &[Primitive<V>], fb: &Framebuffer, clip: (usize, usize, usize, usize)) -> (usize, usize, usize, usize) {
+        if fb.samples() == 1 {
+            return (0, 0, 0, 0);
+        }
+        let (w, h) = (fb.width(), fb.height());
+        primitives.iter()
+            .map(|p| p.bounds(w, h))
+            .filter(|b| b.0 < b.2 && b.1 < b.3)
+            .fold((clip.2, clip.3, clip.0, clip.1), |r, b| {
+                (r.0.min(b.0.max(clip.0)), r.1.min(b.1.max(clip.1)), r.2.max(b.2.min(clip.2)), r.3.max(b.3.min(clip.3)))
+            })
+    }
 
-        for i in (0..indices.len() / 3).map(|x| x * 3) {
-            let p0 =data.get(indices[i]).unwrap();
-            let p1 = data.get(indices[i+1]).unwrap();
-            let p2 = data.get(indices[i+2]).unwrap();
-            self.draw_triangle(p0, p1, p2,true);
+    fn draw_point(&self, p: &VSOutput<V>, id: usize, out: &mut Vec<Primitive<V>>) {
+        //点在视锥外直接丢弃
+        if Self::check_cvv(&p.pos) != 0 {
+            return;
+        }
+        let pos = self.to_ndc(&Self::perspective_div(&p.pos));
+        let va = Self::perspective_correct_to_screen(&p.va, pos.w);
+        out.push(Primitive::Point { pos, va, id });
+    }
+
+    // Clips the segment against every plane, t0 and t1 are where it enters and
+    // leaves the view volume
+    fn draw_line(&self, p0: &VSOutput<V>, p1: &VSOutput<V>, id: usize, out: &mut Vec<Primitive<V>>) {
+        let cc0 = Self::check_cvv(&p0.pos);
+        let cc1 = Self::check_cvv(&p1.pos);
+        if cc0 & cc1 != 0 {
+            return;
+        }
+
+        let (mut t0, mut t1) = (0f32, 1f32);
+        for b in 0..6u8 {
+            let mask = 1 << b;
+            if (cc0 | cc1) & mask == 0 {
+                continue;
+            }
+            let t = Self::compute_t_on_clip_plane(&p0.pos, &p1.pos, Plane::from(b));
+            if cc0 & mask != 0 {
+                t0 = t0.max(t);
+            } else {
+                t1 = t1.min(t);
+            }
+        }
+        if t0 >= t1 {
+            return;
         }
+
+        let clipped = |t: f32| VSOutput::new(Vector::lerp(&p0.pos, &p1.pos, t), Self::clip_lerp(p0, p1, t));
+        let (c0, c1) = (clipped(t0), clipped(t1));
+        let pos0 = self.to_ndc(&Self::perspective_div(&c0.pos));
+        let pos1 = self.to_ndc(&Self::perspective_div(&c1.pos));
+        //flat属性取最后一个顶点的
+        let va0 = Self::perspective_correct_to_screen(&c0.va, pos0.w).with_flat(&p1.va);
+        let va1 = Self::perspective_correct_to_screen(&c1.va, pos1.w).with_flat(&p1.va);
+
+        let bary = [Vector::vec(1f32 - t0, t0, 0f32).scale(pos0.w), Vector::vec(1f32 - t1, t1, 0f32).scale(pos1.w)];
+
+        out.push(Primitive::Line {
+            pos: [pos0, pos1],
+            va: [va0, va1],
+            bary,
+            id,
+        });
     }
 
-    fn draw_triangle(&self, p0: &VSOutput<V>, p1: &VSOutput<V>, p2: &VSOutput<V>,clip:bool) {
+    // `bary` are the barycentric coordinates of the vertices in triangle `id`
+    // as it was assembled, clipping moves them inside it. Flat attributes
+    // come from `provoking`, its last vertex.
+    fn draw_triangle(&self, p: [&VSOutput<V>; 3], bary: [&Vector; 3], provoking: &V, clip: bool, id: usize, out: &mut Vec<Primitive<V>>) {
+        let [p0, p1, p2] = p;
         //背面剔除 https://en.wikipedia.org/wiki/Back-face_culling
-        let m = (p1.pos.x-p0.pos.x)*(p2.pos.y-p0.pos.y)-(p2.pos.x-p0.pos.x)*(p1.pos.y-p0.pos.y);
-        if m < 0f32 {
+        let front_facing = match self.front_facing(&p0.pos, &p1.pos, &p2.pos) {
+            Some(front_facing) => front_facing,
+            None => {
+                //有顶点在相机后面, 朝向在近平面裁剪之后再判断
+                if clip {
+                    self.clip_triangle(p, bary, provoking, Some(Plane::NX), id, out);
+                }
+                return;
+            }
+        };
+        if self.state.rasterizer.culls(front_facing) {
             return;
         }
 
         if clip {
-            self.clip_triangle(p0, p1, p2,Some(Plane::NX));
+            self.clip_triangle(p, bary, provoking, Some(Plane::NX), id, out);
             return;
         }
 
@@ -209,28 +706,201 @@ impl<VS, FS, V> Renderer<VS, FS, V> where
         let pos2 = self.to_ndc(&pos2);
 
         //透视矫正, pos0.w = 1.0f32 / pos0.w
-        let va0= Self::perspective_correct_to_screen(&p0.va,pos0.w);
-        let va1= Self::perspective_correct_to_screen(&p1.va,pos1.w);
-        let va2= Self::perspective_correct_to_screen(&p2.va,pos2.w);
+        let va0= Self::perspective_correct_to_screen(&p0.va,pos0.w).with_flat(provoking);
+        let va1= Self::perspective_correct_to_screen(&p1.va,pos1.w).with_flat(provoking);
+        let va2= Self::perspective_correct_to_screen(&p2.va,pos2.w).with_flat(provoking);
+        let bary = [bary[0].scale(pos0.w), bary[1].scale(pos1.w), bary[2].scale(pos2.w)];
+
+        out.push(Primitive::Triangle {
+            pos: [pos0, pos1, pos2],
+            va: [va0, va1, va2],
+            bary,
+            front_facing,
+            id,
+        });
+    }
 
+    // Draws the part of `prim` inside both the tile and `clip`
+    fn rasterize<F>(tile: &mut Tile, clip: (usize, usize, usize, usize), prim: &Primitive<V>, fs: &F, early_z: bool, state: &PipelineState)
+        where F: Fn(&FragmentInput<V>) -> FragmentOutput
+    {
+        let (x0, y0, x1, y1) = tile.rect();
+        let rect = (x0.max(clip.0), y0.max(clip.1), x1.min(clip.2), y1.min(clip.3));
+        if rect.0 >= rect.2 || rect.1 >= rect.3 {
+            return;
+        }
+        let front_facing = prim.front_facing();
+        let primitive_id = prim.id();
+        let n = tile.samples;
+        let all = ((1u16 << n) - 1) as u8;
+        let early_test = early_z && !state.stencil.enabled;
+        let center = |x: usize, y: usize, z: f32, iw: f32| Vector::new(x as f32 + 0.5f32, y as f32 + 0.5f32, z, iw);
+        match prim {
+            Primitive::Point { pos, va, .. } => {
+                raster::rasterize_point(pos, rect, |x, y| {
+                    let z = [pos.z; 8];
+                    let visible = if early_test { Self::early_depth_test(tile, x, y, all, &z[..n], state) } else { all };
+                    if visible != 0 {
+                        let va = Self::perspective_correct_to_view(va, pos.w);
+                        let zero = va.scale(0f32);
+                        let input = FragmentInput {
+                            va: &va, ddx: &zero, ddy: &zero, front_facing,
+                            frag_coord: center(x, y, pos.z, pos.w), primitive_id, barycentrics: CORNERS[0].clone(),
+                        };
+                        Self::shade(tile, x, y, visible, &z[..n], &input, fs, early_z, state);
+                    }
+                });
+            }
+            Primitive::Line { pos: [pos0, pos1], va, bary, .. } => {
+                raster::rasterize_line([pos0, pos1], rect, |x, y, b| {
+                    let z = [pos0.z * b[0] + pos1.z * b[1]; 8];
+                    let visible = if early_test { Self::early_depth_test(tile, x, y, all, &z[..n], state) } else { all };
+                    if visible != 0 {
+                        let iw = pos0.w * b[0] + pos1.w * b[1];
+                        let va = Self::perspective_correct_to_view(&V::lerp(&va[0], &va[1], b[1]), iw).with_flat(&va[1]);
+                        let zero = va.scale(0f32);
+                        let input = FragmentInput {
+                            va: &va, ddx: &zero, ddy: &zero, front_facing,
+                            frag_coord: center(x, y, z[0], iw), primitive_id,
+                            barycentrics: Vector::lerp(&bary[0], &bary[1], b[1]).scale(1f32 / iw),
+                        };
+                        Self::shade(tile, x, y, visible, &z[..n], &input, fs, early_z, state);
+                    }
+                });
+            }
+            Primitive::Triangle { pos: [pos0, pos1, pos2], va, bary, .. } => {
+                let pattern = raster::sample_pattern(n);
+                raster::rasterize_triangle([pos0, pos1, pos2], rect, pattern, |x, y, coverage, b| {
+                    //每个采样点的深度: 像素中心的深度加上屏幕空间的梯度
+                    let zc = b.map(|b| pos0.z * b[0] + pos1.z * b[1] + pos2.z * b[2]);
+                    let (dzdx, dzdy) = (zc[1] - zc[0], zc[2] - zc[0]);
+                    let mut z = [[0f32; 8]; 4];
+                    let mut visible = [0u8; 4];
+                    for (i, &(dx, dy)) in raster::QUAD.iter().enumerate() {
+                        for (s, &(sx, sy)) in pattern.iter().enumerate() {
+                            z[i][s] = zc[i] + dzdx * sx as f32 / 16f32 + dzdy * sy as f32 / 16f32;
+                        }
+                        visible[i] = if early_test { Self::early_depth_test(tile, x + dx, y + dy, coverage[i], &z[i][..n], state) } else { coverage[i] };
+                    }
+                    if visible == [0u8; 4] {
+                        return;
+                    }
+
+                    //整个quad都插值, 没覆盖的像素只用来求导数
+                    let iw = b.map(|b| pos0.w * b[0] + pos1.w * b[1] + pos2.w * b[2]);
+                    let quad = [0, 1, 2, 3]
+                        .map(|i| Self::perspective_correct_to_view(&raster::interpolate([&va[0], &va[1], &va[2]], b[i]), iw[i]).with_flat(&va[2]));
+                    let ddx = [raster::difference(&quad[0], &quad[1]), raster::difference(&quad[2], &quad[3])];
+                    let ddy = [raster::difference(&quad[0], &quad[2]), raster::difference(&quad[1], &quad[3])];
+                    for (i, &(dx, dy)) in raster::QUAD.iter().enumerate() {
+                        if visible[i] != 0 {
+                            let [b0, b1, b2] = b[i];
+                            let barycentrics = &(&bary[0].scale(b0) + &bary[1].scale(b1)) + &bary[2].scale(b2);
+                            let input = FragmentInput {
+                                va: &quad[i], ddx: &ddx[dy], ddy: &ddy[dx], front_facing,
+                                frag_coord: center(x + dx, y + dy, zc[i], iw[i]), primitive_id,
+                                barycentrics: barycentrics.scale(1f32 / iw[i]),
+                            };
+                            Self::shade(tile, x + dx, y + dy, visible[i], &z[i][..n], &input, fs, early_z, state);
+                        }
+                    }
+                });
+            }
+        }
+    }
 
-        let v0 = (&pos0, &va0);
-        let v1 = (&pos1, &va1);
-        let v2 = (&pos2, &va2);
+    // Stencil and depth tests of the samples of pixel (x, y) in `coverage`,
+    // `z` per sample. Both buffers are updated, returns the samples that passed.
+    #[inline]
+    fn sample_tests(tile: &mut Tile, x: usize, y: usize, coverage: u8, z: &[f32], front_facing: bool, state: &PipelineState) -> u8 {
+        let mut passed = 0u8;
+        for (s, &z) in z.iter().enumerate() {
+            if coverage & (1 << s) != 0 && Self::depth_stencil_test(tile, x, y, s, z, front_facing, state) {
+                passed |= 1 << s;
+            }
+        }
+        passed
+    }
 
-        let s1 = Segment::<V>::new(v0, v1);
-        let s2 = Segment::<V>::new(v0, v2);
-        let s3 = Segment::<V>::new(v1, v2);
+    // The samples in `coverage` that can still pass the depth test, nothing
+    // is written. Spares shading hidden fragments, the real tests come after
+    // the fragment shader. Not used with stencil on, a fragment failing the
+    // depth test may still update the stencil buffer.
+    #[inline]
+    fn early_depth_test(tile: &Tile, x: usize, y: usize, coverage: u8, z: &[f32], state: &PipelineState) -> u8 {
+        let mut passed = 0u8;
+        for (s, &z) in z.iter().enumerate() {
+            if coverage & (1 << s) != 0 && tile.depth_passes(x, y, s, z, &state.depth) {
+                passed |= 1 << s;
+            }
+        }
+        passed
+    }
 
-        let mut tss: Vec<Segment<V>> = vec![s1, s2, s3];
-        //tss[0]长度最长
-        tss.sort_by(|a, b| b.length_y().partial_cmp(&a.length_y()).or(Some(std::cmp::Ordering::Equal)).unwrap());
+    // Stencil and depth tests of one sample, both buffers are updated
+    #[inline]
+    fn depth_stencil_test(tile: &mut Tile, x: usize, y: usize, s: usize, z: f32, front_facing: bool, state: &PipelineState) -> bool {
+        let stencil = state.stencil.face(front_facing);
+        if state.stencil.enabled && !tile.stencil_test(x, y, s, stencil) {
+            tile.stencil_update(x, y, s, stencil, stencil.fail);
+            return false;
+        }
 
-        self.rasterize(&tss[0], &tss[1]);
-        self.rasterize(&tss[0], &tss[2]);
+        let depth_pass = tile.depth_test(x, y, s, z, &state.depth);
+        if state.stencil.enabled {
+            tile.stencil_update(x, y, s, stencil, if depth_pass { stencil.pass } else { stencil.depth_fail });
+        }
+        depth_pass
     }
 
-    fn clip_triangle(&self, p0: &VSOutput<V>, p1: &VSOutput<V>, p2: &VSOutput<V>, plane: Option<Plane>) {
+    // Runs the fragment shader once for pixel (x, y), then the stencil and
+    // depth tests of the samples in `coverage`, `z` is their depth. Each
+    // output is blended into the samples that passed of its color attachment.
+    // A discarded fragment leaves every buffer alone, a depth written by the
+    // shader replaces `z`. With `early_z` the shader promised not to write one.
+    #[allow(clippy::too_many_arguments)]
+    #[inline]
+    fn shade<F>(tile: &mut Tile, x: usize, y: usize, coverage: u8, z: &[f32], input: &FragmentInput<V>, fs: &F, early_z: bool, state: &PipelineState)
+        where F: Fn(&FragmentInput<V>) -> FragmentOutput
+    {
+        let output = fs(input);
+        if output.is_discarded() {
+            return;
+        }
+        //early-z已经按插值的深度剔除过了, 写的深度会被悄悄忽略
+        assert!(output.depth().is_none() || !early_z,
+                "fragment shader wrote a depth without Shader::writes_depth (set_fs_writes_depth for render)");
+        let frag_depth;
+        let z = match output.depth() {
+            Some(d) => {
+                frag_depth = [d; 8];
+                &frag_depth[..z.len()]
+            }
+            None => z,
+        };
+        let coverage = if state.multisample.alpha_to_coverage && tile.samples > 1 {
+            let alpha = output.colors().first().map_or(1f32, |c| c.w);
+            coverage & state.multisample.coverage(alpha, tile.samples)
+        } else {
+            coverage
+        };
+        let coverage = Self::sample_tests(tile, x, y, coverage, z, input.front_facing, state);
+
+        let n = tile.color_attachments();
+        for (i, color) in output.colors().iter().take(n).enumerate() {
+            for s in (0..tile.samples).filter(|s| coverage & (1 << s) != 0) {
+                if state.blend.enabled {
+                    let color = state.blend.blend(color, &tile.get_color(i, x, y, s));
+                    tile.set_color(i, x, y, s, &color);
+                } else {
+                    tile.set_color(i, x, y, s, color);
+                }
+            }
+        }
+    }
+
+    fn clip_triangle(&self, p: [&VSOutput<V>; 3], bary: [&Vector; 3], provoking: &V, plane: Option<Plane>, id: usize, out: &mut Vec<Primitive<V>>) {
+        let [p0, p1, p2] = p;
         let cc0 = Self::check_cvv(&p0.pos);
         let cc1 = Self::check_cvv(&p1.pos);
         let cc2 = Self::check_cvv(&p2.pos);
@@ -245,7 +915,7 @@ impl<VS, FS, V> Renderer<VS, FS, V> where
         let cc_or = cc0 | cc1 | cc2;
 
         if plane.is_none() || cc_or == 0{
-            self.draw_triangle(p0,p1,p2,false);
+            self.draw_triangle(p, bary, provoking, false, id, out);
             return;
         }
 
@@ -253,7 +923,7 @@ impl<VS, FS, V> Renderer<VS, FS, V> where
         let plane = Self::find_next_clip_plane(plane as u8,cc_or);
         let plane = match plane{
             None=>{
-                self.draw_triangle(p0,p1,p2,false);
+                self.draw_triangle(p, bary, provoking, false, id, out);
                 return;
             },
             Some(plane)=>plane
@@ -261,66 +931,61 @@ impl<VS, FS, V> Renderer<VS, FS, V> where
 
         let plane_mask = 1 << u8::from(plane);
         let cc_xor = (cc0 ^ cc1 ^ cc2) & plane_mask;
-        let mut tvs: Vec<&VSOutput<V>> = Vec::with_capacity(3);
 
         if cc_xor == 0 {
             //有两个顶点在当前裁剪平面外
             //tvs[0]在平面内
-            if (cc0 & plane_mask) == 0 {
-                tvs.push(p0);
-                tvs.push(p1);
-                tvs.push(p2);
+            let order = if (cc0 & plane_mask) == 0 {
+                [0, 1, 2]
             } else if (cc1 & plane_mask) == 0 {
-                tvs.push(p1);
-                tvs.push(p2);
-                tvs.push(p0);
+                [1, 2, 0]
             } else {
-                tvs.push(p2);
-                tvs.push(p0);
-                tvs.push(p1);
-            }
+                [2, 0, 1]
+            };
+            let tvs = order.map(|i| p[i]);
+            let tb = order.map(|i| bary[i]);
 
             let t1 = Self::compute_t_on_clip_plane(&tvs[0].pos, &tvs[1].pos, plane);
             let t2 = Self::compute_t_on_clip_plane(&tvs[0].pos, &tvs[2].pos, plane);
 
             let pos01 = Vector::lerp(&tvs[0].pos, &tvs[1].pos, t1);
             let pos02 = Vector::lerp(&tvs[0].pos, &tvs[2].pos, t2);
-            let v01 = V::lerp(&tvs[0].va, &tvs[1].va, t1);
-            let v02 = V::lerp(&tvs[0].va, &tvs[2].va, t2);
+            let v01 = Self::clip_lerp(tvs[0], tvs[1], t1);
+            let v02 = Self::clip_lerp(tvs[0], tvs[2], t2);
             let p01 = VSOutput::new(pos01, v01);
             let p02 = VSOutput::new(pos02, v02);
+            let b01 = Vector::lerp(tb[0], tb[1], t1);
+            let b02 = Vector::lerp(tb[0], tb[2], t2);
 
-            self.clip_triangle(tvs[0], &p01, &p02,plane.next());
+            self.clip_triangle([tvs[0], &p01, &p02], [tb[0], &b01, &b02], provoking, plane.next(), id, out);
         } else {
             //有一个顶点在当前裁剪平面外
             //tvs[0]在平面外
-            if (cc0 & plane_mask) > 0 {
-                tvs.push(p0);
-                tvs.push(p1);
-                tvs.push(p2);
+            let order = if (cc0 & plane_mask) > 0 {
+                [0, 1, 2]
             } else if (cc1 & plane_mask) > 0 {
-                tvs.push(p1);
-                tvs.push(p2);
-                tvs.push(p0);
+                [1, 2, 0]
             } else {
-                tvs.push(p2);
-                tvs.push(p0);
-                tvs.push(p1);
-            }
+                [2, 0, 1]
+            };
+            let tvs = order.map(|i| p[i]);
+            let tb = order.map(|i| bary[i]);
 
             let t1 = Self::compute_t_on_clip_plane(&tvs[1].pos, &tvs[0].pos, plane);
             let t2 = Self::compute_t_on_clip_plane(&tvs[2].pos, &tvs[0].pos, plane);
 
             let pos10 = Vector::lerp(&tvs[1].pos, &tvs[0].pos, t1);
             let pos20 = Vector::lerp(&tvs[2].pos, &tvs[0].pos, t2);
-            let v10 = V::lerp(&tvs[1].va, &tvs[0].va, t1);
-            let v20 = V::lerp(&tvs[2].va, &tvs[0].va, t2);
+            let v10 = Self::clip_lerp(tvs[1], tvs[0], t1);
+            let v20 = Self::clip_lerp(tvs[2], tvs[0], t2);
             let p10 = VSOutput::new(pos10, v10);
             let p20 = VSOutput::new(pos20, v20);
+            let b10 = Vector::lerp(tb[1], tb[0], t1);
+            let b20 = Vector::lerp(tb[2], tb[0], t2);
 
             let np = plane.next();
-            self.clip_triangle(tvs[2], &p20, tvs[1], np);
-            self.clip_triangle(tvs[1], &p20, &p10, np);
+            self.clip_triangle([tvs[2], &p20, tvs[1]], [tb[2], &b20, tb[1]], provoking, np, id, out);
+            self.clip_triangle([tvs[1], &p20, &p10], [tb[1], &b20, &b10], provoking, np, id, out);
         }
     }
 
@@ -335,89 +1000,30 @@ impl<VS, FS, V> Renderer<VS, FS, V> where
         }
     }
 
-    fn rasterize(&self, s1: &Segment<V>, s2: &Segment<V>) {
-        let y_start = s2.s.0.y as usize;
-        let y_end = s2.e.0.y as usize;
-
-        for y in y_start..y_end {
-            let fy = y as f32;
-            let s1ey = s1.e.0.y.floor();
-            let s1sy = s1.s.0.y.floor();
-            let s2ey = s2.e.0.y.floor();
-            let s2sy = s2.s.0.y.floor();
-
-            let t1 = (fy - s2sy) / (s2ey - s2sy);
-            let t2 = (fy - s1sy) / (s1ey - s1sy);
-
-            let mut xp_start = (
-                Vector::lerp(&s2.s.0, &s2.e.0, t1),
-                V::lerp(&s2.s.1, &s2.e.1, t1)
-            );
-            let mut xp_end = (
-                Vector::lerp(&s1.s.0, &s1.e.0, t2),
-                V::lerp(&s1.s.1, &s1.e.1, t2)
-            );
-
-            if xp_start.0.x > xp_end.0.x {
-                swap(&mut xp_start, &mut xp_end);
-            }
-
-            let x_start = xp_start.0.x as usize;
-            let x_end = xp_end.0.x as usize;
-            let x_len = (xp_end.0.x - xp_start.0.x).floor();
-
-            for x in x_start..x_end {
-                let fx = x as f32;
-                let t = (fx - xp_start.0.x.floor()) / x_len;
-                let p = (
-                    Vector::lerp(&xp_start.0, &xp_end.0, t),
-                    V::lerp(&xp_start.1, &xp_end.1, t)
-                );
-
-                //let pos = self.to_ndc(&p.0);
-                if self.set_depth(x as usize, y as usize, p.0.z) {
-                    let va = Self::perspective_correct_to_view(&p.1,p.0.w);
-                    let color = (self.fragment_shader.as_ref().unwrap())(&va);
-                    self.set_color(x as usize, y as usize, &color);
-                }
-            }
-        }
-    }
-
-    #[inline]
-    fn set_depth(&self, x: usize, y: usize, depth: f32) -> bool {
-        let pos = self.width * y + x;
-        let mut db = self.depth_buffer.borrow_mut();
-
-        if pos >= db.len(){
-            println!()
-        }
-
-        if depth < db[pos] {
-            db[pos] = depth;
-            return true;
+    // Attributes where the segment a -> b crosses a clip plane, at `t` in
+    // clip space. Smooth attributes are linear in clip space, noperspective
+    // ones on screen, which needs both ends in front of the eye.
+    fn clip_lerp(a: &VSOutput<V>, b: &VSOutput<V>, t: f32) -> V {
+        let (wa, wb) = (a.pos.w, b.pos.w);
+        if wa <= 0f32 || wb <= 0f32 {
+            return V::lerp(&a.va, &b.va, t);
         }
-        false
-    }
-
-    #[inline]
-    fn set_color(&self, x: usize, y: usize, color: &Vector) {
-        let pos = (self.width * y + x) * 3;
-        let mut cb = self.color_buffer.borrow_mut();
-        let (r, g, b) = ((color.x * 255f32) as u8, (color.y * 255f32) as u8, (color.z * 255f32) as u8);
-        cb[pos + 0] = r;
-        cb[pos + 1] = g;
-        cb[pos + 2] = b;
+        //屏幕空间的插值参数
+        let w = wa + (wb - wa) * t;
+        let s = t * wb / w;
+        let va = V::lerp(&Self::perspective_correct_to_screen(&a.va, 1f32 / wa), &Self::perspective_correct_to_screen(&b.va, 1f32 / wb), s);
+        Self::perspective_correct_to_view(&va, 1f32 / w)
     }
 
+    // Only the smooth attributes, see VertexAttribute::perspective_scale
     #[inline]
     fn perspective_correct_to_screen(va:&V,w:f32)->V{
-        va.scale(w)
+        va.perspective_scale(w)
     }
 
     #[inline]
     fn perspective_correct_to_view(va:&V,w:f32)->V{
-        va.scale(1f32 / w)
+        va.perspective_scale(1f32 / w)
     }
 
     //透视除法
@@ -426,15 +1032,31 @@ impl<VS, FS, V> Renderer<VS, FS, V> where
         Vector::new(
             v.x * iw,
             v.y * iw,
-            v.z,
+            v.z * iw,
             iw,
         )
     }
 
     fn to_ndc(&self, v: &Vector) -> Vector {
-        let nx = (v.x + 1f32) * 0.5f32 * self.width as f32;
-        let ny = (-v.y + 1f32) * 0.5f32 * self.height as f32;
-        Vector::new(nx, ny, v.z,v.w)
+        let (near, far) = self.depth_range;
+        let (vx, vy, vw, vh) = self.viewport_rect(&self.target().borrow());
+        let nx = vx + (v.x + 1f32) * 0.5f32 * vw;
+        let ny = vy + (-v.y + 1f32) * 0.5f32 * vh;
+        let nz = near + (v.z + 1f32) * 0.5f32 * (far - near);
+        Vector::new(nx, ny, nz,v.w)
+    }
+
+    // Sign of the NDC area from the clip space positions, det[x y w] has the
+    // sign of the area times w0 * w1 * w2. None while a w is not positive.
+    fn front_facing(&self, p0: &Vector, p1: &Vector, p2: &Vector) -> Option<bool> {
+        if p0.w <= 0f32 || p1.w <= 0f32 || p2.w <= 0f32 {
+            return None;
+        }
+        let det = p0.x * (p1.y * p2.w - p2.y * p1.w)
+            - p0.y * (p1.x * p2.w - p2.x * p1.w)
+            + p0.w * (p1.x * p2.y - p2.x * p1.y);
+        let ccw = det >= 0f32;
+        Some(ccw == (self.state.rasterizer.front_face == FrontFace::Ccw))
     }
 
     fn find_next_clip_plane(s: u8, code: u8) -> Option<Plane> {
@@ -443,35 +1065,105 @@ impl<VS, FS, V> Renderer<VS, FS, V> where
                 return Some(Plane::from(b));
             }
         }
-        return None;
+        None
     }
 
     fn check_cvv(p: &Vector) -> u8 {
         let mut c = 0u8;
         if p.x < -p.w {
-            c = c | (1 << u8::from(Plane::NX));
+            c |= 1 << u8::from(Plane::NX);
         }
 
         if p.x > p.w {
-            c = c | (1 << u8::from(Plane::X));
+            c |= 1 << u8::from(Plane::X);
         }
 
         if p.y < -p.w {
-            c = c | (1 << u8::from(Plane::NY));
+            c |= 1 << u8::from(Plane::NY);
         }
 
         if p.y > p.w {
-            c = c | (1 << u8::from(Plane::Y));
+            c |= 1 << u8::from(Plane::Y);
         }
 
         if p.z < -p.w {
-            c = c | (1 << u8::from(Plane::NZ));
+            c |= 1 << u8::from(Plane::NZ);
         }
 
         if p.z > p.w {
-            c = c | (1 << u8::from(Plane::Z));
+            c |= 1 << u8::from(Plane::Z);
         }
 
         c
     }
-}
\ No newline at end of file
+}
+
+// 多线程: 图元按tile分箱, 每个tile在一个线程上按提交顺序绘制, 结果和单线程相同
+impl<VS, FS, V, O> Renderer<VS, FS, V, O> where
+    VS: Fn(&V) -> VSOutput<V> + Send + Sync,
+    FS: Fn(&FragmentInput<V>) -> O + Send + Sync,
+    V: VertexAttribute + Send + Sync,
+    O: Into<FragmentOutput>
+{
+    pub fn render_parallel(&self, vertices: &[V]) {
+        self.draw_parallel(&self.closures(), &(), vertices);
+    }
+
+    pub fn render_with_index_parallel(&self, vertices: &[V], indices: &[usize]) {
+        self.draw_with_index_parallel(&self.closures(), &(), vertices, indices);
+    }
+
+    pub fn draw_parallel<S>(&self, shader: &S, uniforms: &S::Uniforms, vertices: &[V])
+        where S: Shader<V> + Sync + ?Sized, S::Uniforms: Sync
+    {
+        let data = self.run_vs_parallel(vertices, &|v: &V| shader.vertex(uniforms, v));
+        let primitives = self.setup_primitives(&data, data.len(), |i| i);
+        self.draw_primitives_parallel(&primitives, &|f: &FragmentInput<V>| shader.fragment(uniforms, f).into(), !shader.writes_depth());
+    }
+
+    pub fn draw_with_index_parallel<S>(&self, shader: &S, uniforms: &S::Uniforms, vertices: &[V], indices: &[usize])
+        where S: Shader<V> + Sync + ?Sized, S::Uniforms: Sync
+    {
+        let data = self.run_vs_parallel(vertices, &|v: &V| shader.vertex(uniforms, v));
+        let primitives = self.setup_primitives(&data, indices.len(), |i| indices[i]);
+        self.draw_primitives_parallel(&primitives, &|f: &FragmentInput<V>| shader.fragment(uniforms, f).into(), !shader.writes_depth());
+    }
+
+    fn run_vs_parallel<F>(&self, vertices: &[V], vs: &F) -> Vec<VSOutput<V>>
+        where F: Fn(&V) -> VSOutput<V> + Sync
+    {
+        let chunk = vertices.len().div_ceil(self.threads);
+        if chunk == 0 {
+            return Vec::new();
+        }
+
+        thread::scope(|s| {
+            let handles: Vec<_> = vertices.chunks(chunk)
+                .map(|part| s.spawn(move || part.iter().map(vs).collect::<Vec<_>>()))
+                .collect();
+            handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+        })
+    }
+
+    fn draw_primitives_parallel<F>(&self, primitives: &[Primitive<V>], fs: &F, early_z: bool)
+        where F: Fn(&FragmentInput<V>) -> FragmentOutput + Sync
+    {
+        let mut fb = self.target().borrow_mut();
+        let state = &self.state;
+        let (w, h) = (fb.width(), fb.height());
+        if w == 0 || h == 0 {
+            return;
+        }
+        let clip = self.draw_rect(&fb);
+
+        let tiles = Tile::split(&mut fb, tile::TILE_SIZE);
+        let bins = tile::bin(primitives.iter().map(|p| p.bounds(w, h)), w, h, tile::TILE_SIZE);
+        tile::run_parallel(tiles, bins, self.threads, |tile, bin| {
+            for &i in bin {
+                Self::rasterize(tile, clip, &primitives[i], fs, early_z, state);
+            }
+        });
+        let rect = Self::touched_rect(primitives, &fb, clip);
+        fb.resolve(rect);
+    }
+}
@@ -0,0 +1,141 @@
+use crate::vector::Vector;
+
+// What happens to texel coordinates outside the texture, per axis
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AddressMode {
+    Repeat,
+    // Every other repetition is mirrored
+    MirroredRepeat,
+    ClampToEdge,
+    // Texels outside the texture are Sampler::border
+    ClampToBorder,
+    // Mirrored once around 0, then clamped to the edge
+    MirrorOnce,
+}
+
+impl AddressMode {
+    // Texel of an axis of `size` texels read for texel coordinate `i`, None
+    // for the border color
+    #[inline]
+    pub(crate) fn apply(self, i: i64, size: i64) -> Option<i64> {
+        match self {
+            AddressMode::Repeat => Some(i.rem_euclid(size)),
+            AddressMode::MirroredRepeat => {
+                let m = i.rem_euclid(size * 2);
+                Some(if m < size { m } else { size * 2 - 1 - m })
+            }
+            AddressMode::ClampToEdge => Some(i.clamp(0, size - 1)),
+            AddressMode::ClampToBorder => if i >= 0 && i < size { Some(i) } else { None },
+            AddressMode::MirrorOnce => Some(if i < 0 { (-1i64).saturating_sub(i) } else { i }.min(size - 1)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    Nearest,
+    // Bilinear between the 4 nearest texel centers
+    Linear,
+}
+
+// How the mip levels are used, None reads level 0 only
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MipmapMode {
+    None,
+    Nearest,
+    Linear,
+}
+
+// How a texture is read, kept apart from the texture so one texture can be
+// sampled with different addressing and filtering. The magnification filter
+// is used for LOD <= 0, the minification filter otherwise.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sampler {
+    pub address_u: AddressMode,
+    pub address_v: AddressMode,
+    pub border: Vector,
+    pub mag_filter: Filter,
+    pub min_filter: Filter,
+    pub mipmap: MipmapMode,
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Sampler {
+            address_u: AddressMode::Repeat,
+            address_v: AddressMode::Repeat,
+            border: Vector::zero(),
+            mag_filter: Filter::Linear,
+            min_filter: Filter::Linear,
+            mipmap: MipmapMode::None,
+        }
+    }
+}
+
+impl Sampler {
+    // Same address mode on both axes, `filter` for magnification and
+    // minification
+    pub fn new(address: AddressMode, filter: Filter, mipmap: MipmapMode) -> Self {
+        Sampler {
+            address_u: address,
+            address_v: address,
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap,
+            ..Default::default()
+        }
+    }
+
+    pub fn nearest() -> Self {
+        Self::new(AddressMode::Repeat, Filter::Nearest, MipmapMode::None)
+    }
+
+    pub fn linear() -> Self {
+        Self::new(AddressMode::Repeat, Filter::Linear, MipmapMode::None)
+    }
+
+    pub fn nearest_mipmap() -> Self {
+        Self::new(AddressMode::Repeat, Filter::Nearest, MipmapMode::Nearest)
+    }
+
+    pub fn trilinear() -> Self {
+        Self::new(AddressMode::Repeat, Filter::Linear, MipmapMode::Linear)
+    }
+
+    pub fn with_address(self, u: AddressMode, v: AddressMode) -> Self {
+        Sampler { address_u: u, address_v: v, ..self }
+    }
+
+    pub fn with_border(self, border: Vector) -> Self {
+        Sampler { border, ..self }
+    }
+
+    pub fn with_filter(self, mag: Filter, min: Filter) -> Self {
+        Sampler { mag_filter: mag, min_filter: min, ..self }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sampler::AddressMode;
+
+    fn addresses(mode: AddressMode) -> Vec<Option<i64>> {
+        (-5..7).map(|i| mode.apply(i, 3)).collect()
+    }
+
+    #[test]
+    fn address_modes() {
+        let some = |v: &[i64]| v.iter().map(|&i| Some(i)).collect::<Vec<_>>();
+        assert_eq!(some(&[1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0]), addresses(AddressMode::Repeat));
+        assert_eq!(some(&[1, 2, 2, 1, 0, 0, 1, 2, 2, 1, 0, 0]), addresses(AddressMode::MirroredRepeat));
+        assert_eq!(some(&[0, 0, 0, 0, 0, 0, 1, 2, 2, 2, 2, 2]), addresses(AddressMode::ClampToEdge));
+        assert_eq!(some(&[2, 2, 2, 1, 0, 0, 1, 2, 2, 2, 2, 2]), addresses(AddressMode::MirrorOnce));
+        let border = addresses(AddressMode::ClampToBorder);
+        assert_eq!(vec![None; 5], border[..5]);
+        assert_eq!(some(&[0, 1, 2]), border[5..8]);
+        assert_eq!(vec![None; 4], border[8..]);
+        // Nothing overflows at the ends of i64
+        assert_eq!(Some(2), AddressMode::MirrorOnce.apply(i64::MIN, 3));
+        assert_eq!(Some(1), AddressMode::MirroredRepeat.apply(i64::MAX, 3));
+    }
+}
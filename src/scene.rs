@@ -0,0 +1,69 @@
+use crate::vector::Vector;
+use crate::vertex::Vertex;
+use crate::matrix::Matrix;
+use crate::texture::Texture;
+use crate::renderer::{VSOutput, FragmentInput};
+use crate::shader::Shader;
+use std::f32;
+
+// The textured cube shared by the demo binaries
+pub fn cube() -> (Vec<Vertex>, Vec<usize>) {
+    let white = || Vector::new(1.0, 1.0, 1.0, 1.0);
+    let vertices = vec![
+        Vertex{pos:Vector::point(-1.0,-1.0,1.0),uv: Vector::vec2(1.0,0.0),color: white(),normal: Vector::zero()},
+        Vertex{pos:Vector::point(1.0,-1.0,1.0),uv: Vector::vec2(0.0,0.0),color: white(),normal: Vector::zero()},
+        Vertex{pos:Vector::point(1.0,1.0,1.0),uv: Vector::vec2(0.0,1.0),color: white(),normal: Vector::zero()},
+        Vertex{pos:Vector::point(-1.0,1.0,1.0),uv: Vector::vec2(1.0,1.0),color: white(),normal: Vector::zero()},
+        Vertex{pos:Vector::point(-1.0,-1.0,-1.0),uv: Vector::vec2(0.0,0.0),color: white(),normal: Vector::zero()},
+        Vertex{pos:Vector::point(-1.0,1.0,-1.0),uv: Vector::vec2(0.0,1.0),color: white(),normal: Vector::zero()},
+        Vertex{pos:Vector::point(1.0,1.0,-1.0),uv: Vector::vec2(1.0,1.0),color: white(),normal: Vector::zero()},
+        Vertex{pos:Vector::point(1.0,-1.0,-1.0),uv: Vector::vec2(1.0,0.0),color: white(),normal: Vector::zero()},
+    ];
+
+    let indices = vec![
+        0, 1, 2, 0, 2, 3, // Quad 0
+        4, 5, 6, 4, 6, 7, // Quad 1
+        5, 3, 2, 5, 2, 6, // Quad 2
+        4, 7, 1, 4, 1, 0, // Quad 3
+        7, 6, 2, 7, 2, 1, // Quad 4
+        4, 0, 3, 4, 3, 5  // Quad 5
+    ];
+
+    (vertices, indices)
+}
+
+// Projection * view of the camera orbiting the cube, `t` is the demo's frame angle
+pub fn orbit_camera(t: f32, aspect: f32) -> Matrix {
+    let p = Matrix::perspective(f32::consts::PI * 0.5f32, aspect, 0.1, 1000.0);
+    let view = Matrix::look_at(
+        &Vector::point(-f32::sin(t), 1f32, 2f32 * f32::cos(t)),
+        &Vector::point(0.0,0.0,0.0),
+        &Vector::vec(0f32, 1f32, 0f32));
+    &p * &view
+}
+
+// The demo material, trilinear filtered texture. Uniforms are projection *
+// view, e.g. from orbit_camera.
+pub struct TexturedShader {
+    pub texture: Texture,
+}
+
+impl Shader<Vertex> for TexturedShader {
+    type Uniforms = Matrix;
+    type Output = Vector;
+
+    fn vertex(&self, pv: &Matrix, v: &Vertex) -> VSOutput<Vertex> {
+        VSOutput::new(
+            pv.apply(&v.pos),
+            Vertex {
+                pos: v.pos.clone(),
+                color: v.color.clone(),
+                normal: Vector::zero(),
+                uv: v.uv.clone(),
+            })
+    }
+
+    fn fragment(&self, _: &Matrix, f: &FragmentInput<Vertex>) -> Vector {
+        self.texture.get_color_trilinear(f.va.uv.x, f.va.uv.y, &f.ddx.uv, &f.ddy.uv)
+    }
+}
@@ -0,0 +1,64 @@
+use crate::renderer::{VSOutput, FragmentInput, FragmentOutput};
+use crate::vertex::VertexAttribute;
+use std::marker::PhantomData;
+
+// A shader program, the vertex and fragment stages of one material. Values
+// that change between draws, like the camera matrix, go in Uniforms and are
+// passed to the draw call (Renderer::draw), so a program is built once and
+// any number of them can draw into the same renderer.
+//
+// Object safe once the associated types are named, e.g.
+// &dyn Shader<Vertex, Uniforms = Matrix, Output = Vector>
+pub trait Shader<V: VertexAttribute> {
+    type Uniforms;
+    // Vector for one color output, [Vector; N] for N, see FragmentOutput
+    type Output: Into<FragmentOutput>;
+
+    fn vertex(&self, uniforms: &Self::Uniforms, v: &V) -> VSOutput<V>;
+
+    fn fragment(&self, uniforms: &Self::Uniforms, f: &FragmentInput<V>) -> Self::Output;
+
+    // True when fragment() may set a depth (FragmentOutput::with_depth).
+    // Otherwise fragments behind the depth buffer are dropped before the
+    // fragment stage runs, and writing a depth anyway panics.
+    fn writes_depth(&self) -> bool {
+        false
+    }
+}
+
+// The closures given to set_vs and set_fs as a program without uniforms,
+// writes_depth comes from Renderer::set_fs_writes_depth
+pub(crate) struct Closures<'a, VS, FS, O> {
+    vs: &'a VS,
+    fs: &'a FS,
+    writes_depth: bool,
+    _phantom: PhantomData<fn() -> O>,
+}
+
+impl<'a, VS, FS, O> Closures<'a, VS, FS, O> {
+    pub(crate) fn new(vs: &'a VS, fs: &'a FS, writes_depth: bool) -> Self {
+        Closures { vs, fs, writes_depth, _phantom: PhantomData }
+    }
+}
+
+impl<VS, FS, V, O> Shader<V> for Closures<'_, VS, FS, O> where
+    VS: Fn(&V) -> VSOutput<V>,
+    FS: Fn(&FragmentInput<V>) -> O,
+    V: VertexAttribute,
+    O: Into<FragmentOutput>
+{
+    type Uniforms = ();
+    type Output = O;
+
+    fn vertex(&self, _: &(), v: &V) -> VSOutput<V> {
+        (self.vs)(v)
+    }
+
+    fn fragment(&self, _: &(), f: &FragmentInput<V>) -> O {
+        (self.fs)(f)
+    }
+
+    fn writes_depth(&self) -> bool {
+        self.writes_depth
+    }
+}
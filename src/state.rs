@@ -0,0 +1,557 @@
+use crate::vector::Vector;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlendFactor {
+    Zero,
+    One,
+    SrcColor,
+    OneMinusSrcColor,
+    DstColor,
+    OneMinusDstColor,
+    SrcAlpha,
+    OneMinusSrcAlpha,
+    DstAlpha,
+    OneMinusDstAlpha,
+    ConstantColor,
+    OneMinusConstantColor,
+    ConstantAlpha,
+    OneMinusConstantAlpha,
+    SrcAlphaSaturate,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlendOp {
+    // src * sf + dst * df
+    Add,
+    // src * sf - dst * df
+    Subtract,
+    // dst * df - src * sf
+    ReverseSubtract,
+    // min(src, dst), factors are ignored
+    Min,
+    // max(src, dst), factors are ignored
+    Max,
+}
+
+// Blending of the fragment color (src) with the color buffer (dst), rgb and
+// alpha have their own factors and equation like glBlendFuncSeparate
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlendState {
+    pub enabled: bool,
+    pub src_color: BlendFactor,
+    pub dst_color: BlendFactor,
+    pub color_op: BlendOp,
+    pub src_alpha: BlendFactor,
+    pub dst_alpha: BlendFactor,
+    pub alpha_op: BlendOp,
+    pub constant: Vector,
+}
+
+impl Default for BlendState {
+    fn default() -> Self {
+        Self::replace()
+    }
+}
+
+impl BlendState {
+    // Blending off, the fragment overwrites the pixel
+    pub fn replace() -> Self {
+        BlendState {
+            enabled: false,
+            src_color: BlendFactor::One,
+            dst_color: BlendFactor::Zero,
+            color_op: BlendOp::Add,
+            src_alpha: BlendFactor::One,
+            dst_alpha: BlendFactor::Zero,
+            alpha_op: BlendOp::Add,
+            constant: Vector::zero(),
+        }
+    }
+
+    pub fn new(src: BlendFactor, dst: BlendFactor, op: BlendOp) -> Self {
+        BlendState {
+            enabled: true,
+            src_color: src,
+            dst_color: dst,
+            color_op: op,
+            src_alpha: src,
+            dst_alpha: dst,
+            alpha_op: op,
+            constant: Vector::zero(),
+        }
+    }
+
+    // Classic transparency: src * a + dst * (1 - a)
+    pub fn alpha() -> Self {
+        Self::new(BlendFactor::SrcAlpha, BlendFactor::OneMinusSrcAlpha, BlendOp::Add)
+    }
+
+    // Particles and glow: src * a + dst
+    pub fn additive() -> Self {
+        Self::new(BlendFactor::SrcAlpha, BlendFactor::One, BlendOp::Add)
+    }
+
+    // Separate factors and equation for the alpha channel
+    pub fn with_alpha(mut self, src: BlendFactor, dst: BlendFactor, op: BlendOp) -> Self {
+        self.src_alpha = src;
+        self.dst_alpha = dst;
+        self.alpha_op = op;
+        self
+    }
+
+    // Color used by the Constant* factors
+    pub fn with_constant(mut self, constant: Vector) -> Self {
+        self.constant = constant;
+        self
+    }
+
+    fn factor(&self, f: BlendFactor, src: &Vector, dst: &Vector) -> Vector {
+        let c = &self.constant;
+        match f {
+            BlendFactor::Zero => Vector::new(0.0, 0.0, 0.0, 0.0),
+            BlendFactor::One => Vector::new(1.0, 1.0, 1.0, 1.0),
+            BlendFactor::SrcColor => src.clone(),
+            BlendFactor::OneMinusSrcColor => Vector::new(1.0 - src.x, 1.0 - src.y, 1.0 - src.z, 1.0 - src.w),
+            BlendFactor::DstColor => dst.clone(),
+            BlendFactor::OneMinusDstColor => Vector::new(1.0 - dst.x, 1.0 - dst.y, 1.0 - dst.z, 1.0 - dst.w),
+            BlendFactor::SrcAlpha => Vector::new(src.w, src.w, src.w, src.w),
+            BlendFactor::OneMinusSrcAlpha => {
+                let a = 1.0 - src.w;
+                Vector::new(a, a, a, a)
+            }
+            BlendFactor::DstAlpha => Vector::new(dst.w, dst.w, dst.w, dst.w),
+            BlendFactor::OneMinusDstAlpha => {
+                let a = 1.0 - dst.w;
+                Vector::new(a, a, a, a)
+            }
+            BlendFactor::ConstantColor => c.clone(),
+            BlendFactor::OneMinusConstantColor => Vector::new(1.0 - c.x, 1.0 - c.y, 1.0 - c.z, 1.0 - c.w),
+            BlendFactor::ConstantAlpha => Vector::new(c.w, c.w, c.w, c.w),
+            BlendFactor::OneMinusConstantAlpha => {
+                let a = 1.0 - c.w;
+                Vector::new(a, a, a, a)
+            }
+            BlendFactor::SrcAlphaSaturate => {
+                let f = src.w.min(1.0 - dst.w);
+                Vector::new(f, f, f, 1.0)
+            }
+        }
+    }
+
+    fn apply(op: BlendOp, s: f32, sf: f32, d: f32, df: f32) -> f32 {
+        match op {
+            BlendOp::Add => s * sf + d * df,
+            BlendOp::Subtract => s * sf - d * df,
+            BlendOp::ReverseSubtract => d * df - s * sf,
+            BlendOp::Min => s.min(d),
+            BlendOp::Max => s.max(d),
+        }
+    }
+
+    pub fn blend(&self, src: &Vector, dst: &Vector) -> Vector {
+        if !self.enabled {
+            return src.clone();
+        }
+
+        let sc = self.factor(self.src_color, src, dst);
+        let dc = self.factor(self.dst_color, src, dst);
+        let sa = self.factor(self.src_alpha, src, dst);
+        let da = self.factor(self.dst_alpha, src, dst);
+
+        Vector::new(
+            Self::apply(self.color_op, src.x, sc.x, dst.x, dc.x),
+            Self::apply(self.color_op, src.y, sc.y, dst.y, dc.y),
+            Self::apply(self.color_op, src.z, sc.z, dst.z, dc.z),
+            Self::apply(self.alpha_op, src.w, sa.w, dst.w, da.w),
+        )
+    }
+}
+
+// Comparison used by the depth test, `value` is the incoming fragment and
+// `reference` what is stored in the buffer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompareFunc {
+    Never,
+    Less,
+    LessEqual,
+    Equal,
+    Greater,
+    GreaterEqual,
+    NotEqual,
+    Always,
+}
+
+impl CompareFunc {
+    #[inline]
+    pub fn test<T: PartialOrd>(self, value: T, reference: T) -> bool {
+        match self {
+            CompareFunc::Never => false,
+            CompareFunc::Less => value < reference,
+            CompareFunc::LessEqual => value <= reference,
+            CompareFunc::Equal => value == reference,
+            CompareFunc::Greater => value > reference,
+            CompareFunc::GreaterEqual => value >= reference,
+            CompareFunc::NotEqual => value != reference,
+            CompareFunc::Always => true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DepthState {
+    pub func: CompareFunc,
+    // Passing fragments write their depth
+    pub write: bool,
+}
+
+impl Default for DepthState {
+    fn default() -> Self {
+        DepthState {
+            func: CompareFunc::Less,
+            write: true,
+        }
+    }
+}
+
+impl DepthState {
+    pub fn new(func: CompareFunc, write: bool) -> Self {
+        DepthState { func, write }
+    }
+
+    // Every fragment passes and the depth buffer is left alone
+    pub fn disabled() -> Self {
+        Self::new(CompareFunc::Always, false)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StencilOp {
+    Keep,
+    Zero,
+    // Write the reference value
+    Replace,
+    // +1, clamped to 255
+    IncrClamp,
+    // -1, clamped to 0
+    DecrClamp,
+    Invert,
+    // +1, 255 wraps to 0
+    IncrWrap,
+    // -1, 0 wraps to 255
+    DecrWrap,
+}
+
+impl StencilOp {
+    #[inline]
+    pub fn apply(self, value: u8, reference: u8) -> u8 {
+        match self {
+            StencilOp::Keep => value,
+            StencilOp::Zero => 0,
+            StencilOp::Replace => reference,
+            StencilOp::IncrClamp => value.saturating_add(1),
+            StencilOp::DecrClamp => value.saturating_sub(1),
+            StencilOp::Invert => !value,
+            StencilOp::IncrWrap => value.wrapping_add(1),
+            StencilOp::DecrWrap => value.wrapping_sub(1),
+        }
+    }
+}
+
+// Stencil state of one face. The test passes when
+// (reference & read_mask) func (stencil & read_mask), only the bits in
+// write_mask are changed by the ops.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StencilFaceState {
+    pub func: CompareFunc,
+    pub reference: u8,
+    pub read_mask: u8,
+    pub write_mask: u8,
+    // Stencil test failed
+    pub fail: StencilOp,
+    // Stencil test passed, depth test failed
+    pub depth_fail: StencilOp,
+    // Both passed
+    pub pass: StencilOp,
+}
+
+impl Default for StencilFaceState {
+    fn default() -> Self {
+        StencilFaceState {
+            func: CompareFunc::Always,
+            reference: 0,
+            read_mask: 0xff,
+            write_mask: 0xff,
+            fail: StencilOp::Keep,
+            depth_fail: StencilOp::Keep,
+            pass: StencilOp::Keep,
+        }
+    }
+}
+
+impl StencilFaceState {
+    pub fn new(func: CompareFunc, reference: u8) -> Self {
+        StencilFaceState {
+            func,
+            reference,
+            ..Default::default()
+        }
+    }
+
+    // fail, depth fail and pass ops
+    pub fn with_ops(mut self, fail: StencilOp, depth_fail: StencilOp, pass: StencilOp) -> Self {
+        self.fail = fail;
+        self.depth_fail = depth_fail;
+        self.pass = pass;
+        self
+    }
+
+    pub fn with_masks(mut self, read_mask: u8, write_mask: u8) -> Self {
+        self.read_mask = read_mask;
+        self.write_mask = write_mask;
+        self
+    }
+
+    #[inline]
+    pub fn test(&self, stencil: u8) -> bool {
+        self.func.test(self.reference & self.read_mask, stencil & self.read_mask)
+    }
+
+    #[inline]
+    pub fn update(&self, stencil: u8, op: StencilOp) -> u8 {
+        let v = op.apply(stencil, self.reference);
+        (stencil & !self.write_mask) | (v & self.write_mask)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StencilState {
+    pub enabled: bool,
+    pub front: StencilFaceState,
+    pub back: StencilFaceState,
+}
+
+impl StencilState {
+    // Same state for both faces
+    pub fn new(face: StencilFaceState) -> Self {
+        StencilState {
+            enabled: true,
+            front: face.clone(),
+            back: face,
+        }
+    }
+
+    pub fn separate(front: StencilFaceState, back: StencilFaceState) -> Self {
+        StencilState {
+            enabled: true,
+            front,
+            back,
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub fn face(&self, front_facing: bool) -> &StencilFaceState {
+        if front_facing { &self.front } else { &self.back }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CullMode {
+    None,
+    Front,
+    Back,
+    FrontAndBack,
+}
+
+// Winding of front faces in NDC (y up)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrontFace {
+    Ccw,
+    Cw,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RasterizerState {
+    pub cull: CullMode,
+    pub front_face: FrontFace,
+}
+
+impl Default for RasterizerState {
+    fn default() -> Self {
+        RasterizerState {
+            cull: CullMode::Back,
+            front_face: FrontFace::Ccw,
+        }
+    }
+}
+
+impl RasterizerState {
+    pub fn new(cull: CullMode, front_face: FrontFace) -> Self {
+        RasterizerState { cull, front_face }
+    }
+
+    // Both faces are drawn, for double-sided materials
+    pub fn double_sided() -> Self {
+        Self::new(CullMode::None, FrontFace::Ccw)
+    }
+
+    #[inline]
+    pub fn culls(&self, front_facing: bool) -> bool {
+        match self.cull {
+            CullMode::None => false,
+            CullMode::Front => front_facing,
+            CullMode::Back => !front_facing,
+            CullMode::FrontAndBack => true,
+        }
+    }
+}
+
+// Where NDC lands in the framebuffer: the top-left corner and size in
+// pixels, and the depth range NDC z in [-1, 1] is mapped to
+#[derive(Clone, Debug, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub min_depth: f32,
+    pub max_depth: f32,
+}
+
+impl Viewport {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Viewport { x, y, width, height, min_depth: 0f32, max_depth: 1f32 }
+    }
+
+    pub fn with_depth(self, min_depth: f32, max_depth: f32) -> Self {
+        Viewport { min_depth, max_depth, ..self }
+    }
+}
+
+// Only has an effect on a multisampled framebuffer
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MultisampleState {
+    // The alpha of the first fragment output decides how many samples of
+    // the pixel are written, for cutout foliage without sorting. Depth and
+    // stencil are then tested after the fragment shader.
+    pub alpha_to_coverage: bool,
+}
+
+impl MultisampleState {
+    pub fn new(alpha_to_coverage: bool) -> Self {
+        MultisampleState { alpha_to_coverage }
+    }
+
+    // Sample mask for `alpha` out of `samples`, all samples when alpha to
+    // coverage is off
+    #[inline]
+    pub fn coverage(&self, alpha: f32, samples: usize) -> u8 {
+        let n = if self.alpha_to_coverage {
+            (alpha.clamp(0f32, 1f32) * samples as f32).round() as usize
+        } else {
+            samples
+        };
+        ((1u16 << n) - 1) as u8
+    }
+}
+
+// Fixed-function state read by the per-pixel stage of a draw call
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PipelineState {
+    pub blend: BlendState,
+    pub depth: DepthState,
+    pub stencil: StencilState,
+    pub rasterizer: RasterizerState,
+    pub multisample: MultisampleState,
+}
+
+#[cfg(test)]
+mod test {
+    use crate::state::{BlendState, BlendFactor, BlendOp, CompareFunc, StencilOp, StencilFaceState, MultisampleState};
+    use crate::vector::Vector;
+
+    #[test]
+    fn disabled_replaces() {
+        let src = Vector::new(0.1, 0.2, 0.3, 0.0);
+        let dst = Vector::new(1.0, 1.0, 1.0, 1.0);
+        assert_eq!(src, BlendState::default().blend(&src, &dst));
+    }
+
+    #[test]
+    fn alpha_blend() {
+        let src = Vector::new(1.0, 0.0, 0.0, 0.25);
+        let dst = Vector::new(0.0, 0.0, 1.0, 1.0);
+        let c = BlendState::alpha().blend(&src, &dst);
+        assert_eq!(Vector::new(0.25, 0.0, 0.75, 0.8125), c);
+    }
+
+    #[test]
+    fn equations() {
+        let src = Vector::new(0.5, 0.25, 1.0, 1.0);
+        let dst = Vector::new(0.25, 0.5, 0.5, 1.0);
+        let state = |op| BlendState::new(BlendFactor::One, BlendFactor::One, op);
+        assert_eq!(Vector::new(0.75, 0.75, 1.5, 2.0), state(BlendOp::Add).blend(&src, &dst));
+        assert_eq!(Vector::new(0.25, -0.25, 0.5, 0.0), state(BlendOp::Subtract).blend(&src, &dst));
+        assert_eq!(Vector::new(-0.25, 0.25, -0.5, 0.0), state(BlendOp::ReverseSubtract).blend(&src, &dst));
+        assert_eq!(Vector::new(0.25, 0.25, 0.5, 1.0), state(BlendOp::Min).blend(&src, &dst));
+        assert_eq!(Vector::new(0.5, 0.5, 1.0, 1.0), state(BlendOp::Max).blend(&src, &dst));
+    }
+
+    #[test]
+    fn multiply() {
+        let src = Vector::new(0.5, 0.5, 0.5, 1.0);
+        let dst = Vector::new(1.0, 0.5, 0.0, 1.0);
+        let state = BlendState::new(BlendFactor::DstColor, BlendFactor::Zero, BlendOp::Add);
+        assert_eq!(Vector::new(0.5, 0.25, 0.0, 1.0), state.blend(&src, &dst));
+    }
+
+    #[test]
+    fn compare_funcs() {
+        let funcs = [
+            (CompareFunc::Never, [false, false, false]),
+            (CompareFunc::Less, [true, false, false]),
+            (CompareFunc::LessEqual, [true, true, false]),
+            (CompareFunc::Equal, [false, true, false]),
+            (CompareFunc::Greater, [false, false, true]),
+            (CompareFunc::GreaterEqual, [false, true, true]),
+            (CompareFunc::NotEqual, [true, false, true]),
+            (CompareFunc::Always, [true, true, true]),
+        ];
+        for (func, expected) in funcs.iter() {
+            assert_eq!(*expected, [func.test(0.25, 0.5), func.test(0.5, 0.5), func.test(0.75, 0.5)], "{:?}", func);
+        }
+    }
+
+    #[test]
+    fn stencil_ops() {
+        assert_eq!(7, StencilOp::Keep.apply(7, 3));
+        assert_eq!(0, StencilOp::Zero.apply(7, 3));
+        assert_eq!(3, StencilOp::Replace.apply(7, 3));
+        assert_eq!(255, StencilOp::IncrClamp.apply(255, 3));
+        assert_eq!(0, StencilOp::DecrClamp.apply(0, 3));
+        assert_eq!(0b1111_0000, StencilOp::Invert.apply(0b0000_1111, 3));
+        assert_eq!(0, StencilOp::IncrWrap.apply(255, 3));
+        assert_eq!(255, StencilOp::DecrWrap.apply(0, 3));
+    }
+
+    #[test]
+    fn stencil_masks() {
+        let face = StencilFaceState::new(CompareFunc::Equal, 0b0101).with_masks(0b0011, 0b1100);
+        assert!(face.test(0b1001));
+        assert!(!face.test(0b0110));
+        assert_eq!(0b0111, face.update(0b1011, StencilOp::Replace));
+    }
+
+    #[test]
+    fn alpha_to_coverage() {
+        let a2c = MultisampleState::new(true);
+        assert_eq!(0b0000, a2c.coverage(0.1, 4));
+        assert_eq!(0b0011, a2c.coverage(0.5, 4));
+        assert_eq!(0b1111, a2c.coverage(1.5, 4));
+        assert_eq!(0xff, a2c.coverage(1.0, 8));
+        assert_eq!(0b1111, MultisampleState::default().coverage(0.0, 4));
+    }
+}
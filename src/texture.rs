@@ -1,59 +1,326 @@
-use image::{ImageError, DynamicImage, GenericImageView};
+use image::{ImageError, DynamicImage, GenericImageView, ImageBuffer, Rgba};
+use image::error::{ParameterError, ParameterErrorKind};
 use crate::vector::Vector;
+use crate::sampler::{Sampler, Filter, MipmapMode};
+
+// Texels are kept as f32 RGBA, so render targets and depth buffers can be
+// sampled at full precision
+type Level = ImageBuffer<Rgba<f32>, Vec<f32>>;
 
 pub struct Texture{
-    pub image:DynamicImage
+    // Level 0 is the image, every level halves the size down to 1x1
+    levels:Vec<Level>,
+}
+
+// Box filter: every texel is the average of the 2x2 texels it covers in the
+// previous level, the last row or column is repeated for odd sizes
+fn downsample(src: &Level) -> Level {
+    let (w, h) = src.dimensions();
+    Level::from_fn((w / 2).max(1), (h / 2).max(1), |x, y| {
+        let (x0, y0) = ((x * 2).min(w - 1), (y * 2).min(h - 1));
+        let (x1, y1) = ((x * 2 + 1).min(w - 1), (y * 2 + 1).min(h - 1));
+        let mut sum = [0f32; 4];
+        for &(sx, sy) in &[(x0, y0), (x1, y0), (x0, y1), (x1, y1)] {
+            let p = src.get_pixel(sx, sy);
+            for (s, c) in sum.iter_mut().zip(p.0.iter()) {
+                *s += *c;
+            }
+        }
+        Rgba([sum[0] * 0.25f32, sum[1] * 0.25f32, sum[2] * 0.25f32, sum[3] * 0.25f32])
+    })
+}
+
+// Bad dimensions of a loaded file
+pub(crate) fn invalid(msg: &str) -> ImageError {
+    ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::Generic(msg.to_string())))
 }
 
 impl Texture{
-    pub fn open(path:&str) ->Result<Self,ImageError>{
-        image::open(path).map(|image|{
-            Texture{
-                image
+    pub fn new(image:DynamicImage)->Self{
+        let rgba = image.to_rgba();
+        let (w, h) = rgba.dimensions();
+        Self::from_rgba(w, h, rgba.into_raw().into_iter().map(|c| c as f32 / 255f32).collect())
+    }
+
+    // `texels` is RGBA, row by row from the top, 4 * width * height values.
+    // Panics when width or height is 0.
+    pub fn from_rgba(width:u32,height:u32,texels:Vec<f32>)->Self{
+        assert!(width > 0 && height > 0, "a texture can't be empty ({}x{})", width, height);
+        let mut levels = vec![Level::from_raw(width, height, texels).expect("texels do not match the size")];
+        while let Some(last) = levels.last() {
+            if last.width() == 1 && last.height() == 1 {
+                break;
             }
-        })
+            levels.push(downsample(last));
+        }
+        Texture{
+            levels,
+        }
     }
 
+    pub fn open(path:&str) ->Result<Self,ImageError>{
+        let image = image::open(path)?;
+        if image.width() == 0 || image.height() == 0 {
+            return Err(invalid("the image is empty"));
+        }
+        Ok(Texture::new(image))
+    }
+
+    pub fn width(&self)->u32{
+        self.levels[0].width()
+    }
+
+    pub fn height(&self)->u32{
+        self.levels[0].height()
+    }
+
+    // Number of mip levels, level 0 included
+    pub fn levels(&self)->usize{
+        self.levels.len()
+    }
+
+    // Level of detail for the screen-space derivatives of the texture
+    // coordinates, log2 of the texels covered by one pixel along the longer
+    // axis of the footprint. Negative when the texture is magnified.
+    pub fn lod(&self,ddx:&Vector,ddy:&Vector)->f32{
+        let (w, h) = (self.levels[0].width() as f32, self.levels[0].height() as f32);
+        let len = |d: &Vector| ((d.x * w).powi(2) + (d.y * h).powi(2)).sqrt();
+        len(ddx).max(len(ddy)).log2()
+    }
+
+    // Texel (x, y) of a level, addressed by the sampler
     #[inline]
-    fn get_color(&self,x:u32,y:u32)->Vector{
-        let x = x % self.image.width();
-        let y = y % self.image.height();
-        let c = self.image.get_pixel(x,y);
-        Vector::new(
-            c.0[0] as f32/ 255f32,
-            c.0[1] as f32/ 255f32,
-            c.0[2] as f32/ 255f32,
-            c.0[3] as f32/ 255f32,
-        )
+    fn get_color(&self,level:usize,x:i64,y:i64,sampler:&Sampler)->Vector{
+        let image = &self.levels[level];
+        let x = sampler.address_u.apply(x, image.width() as i64);
+        let y = sampler.address_v.apply(y, image.height() as i64);
+        let (x, y) = match (x, y) {
+            (Some(x), Some(y)) => (x, y),
+            _ => return sampler.border.clone(),
+        };
+        self.texel(level,x as u32,y as u32)
+    }
+
+    // Size of a mip level
+    pub(crate) fn size(&self,level:usize)->(u32,u32){
+        self.levels[level].dimensions()
     }
 
+    // Texel of a level without addressing, x and y have to be inside it
     #[inline]
-    pub fn get_color_nearest(&self,x:f32,y:f32)->Vector{
-        let ix = (x*self.image.width() as f32) as u32;
-        let iy = ((1f32 - y)*self.image.height() as f32) as u32;
-        self.get_color(ix,iy)
+    pub(crate) fn texel(&self,level:usize,x:u32,y:u32)->Vector{
+        let c = self.levels[level].get_pixel(x,y);
+        Vector::new(c.0[0], c.0[1], c.0[2], c.0[3])
     }
 
-    pub fn get_color_linear(&self, x:f32, y:f32) ->Vector {
-        let fx = (x%1f32).abs() * self.image.width() as f32;
-        let fy = ((1f32 - y)%1f32).abs() * self.image.height() as f32;
+    // Texel coordinate `f` kept far inside i64 so the addressing math can't
+    // overflow. Floats that large have no fraction left, so only inf and
+    // absurd uv move, NaN reads texel 0.
+    #[inline]
+    fn bounded(f:f32)->f32{
+        const LIMIT: f32 = 1e18;
+        if f.is_nan() { 0f32 } else { f.clamp(-LIMIT, LIMIT) }
+    }
+
+    //v向上, 图片的行向下
+    fn nearest(&self,level:usize,x:f32,y:f32,sampler:&Sampler)->Vector{
+        let (w, h) = self.levels[level].dimensions();
+        let ix = Self::bounded(x*w as f32).floor() as i64;
+        let iy = Self::bounded((1f32 - y)*h as f32).floor() as i64;
+        self.get_color(level,ix,iy,sampler)
+    }
+
+    // Texel centers are at half integers
+    fn linear(&self,level:usize,x:f32,y:f32,sampler:&Sampler)->Vector{
+        let (w, h) = self.levels[level].dimensions();
+        let fx = Self::bounded(x * w as f32 - 0.5f32);
+        let fy = Self::bounded((1f32 - y) * h as f32 - 0.5f32);
         let ffx = fx.floor();
         let ffy = fy.floor();
         let dx = fx - ffx;// dx => (0 - 1)
         let dy = fy - ffy;
 
-        let ix0 = ffx as u32;
-        let iy0 = ffy as u32;
-        let ix1 = (ix0 + 1) % self.image.width();
-        let iy1 = (iy0 + 1) % self.image.height();
+        let ix0 = ffx as i64;
+        let iy0 = ffy as i64;
 
-        let c00 = self.get_color(ix0,iy0);
-        let c10 = self.get_color(ix1,iy0);
-        let c01 = self.get_color(ix0,iy1);
-        let c11 = self.get_color(ix1,iy1);
+        let c00 = self.get_color(level,ix0,iy0,sampler);
+        let c10 = self.get_color(level,ix0 + 1,iy0,sampler);
+        let c01 = self.get_color(level,ix0,iy0 + 1,sampler);
+        let c11 = self.get_color(level,ix0 + 1,iy0 + 1,sampler);
 
         let cx0 = &c00 + &((&c10-&c00).scale(dx));
         let cx1 = &c01 + &((&c11-&c01).scale(dx));
         &cx0 + &((&cx1-&cx0).scale(dy))
     }
-}
\ No newline at end of file
+
+    fn filter(&self,level:usize,x:f32,y:f32,filter:Filter,sampler:&Sampler)->Vector{
+        match filter {
+            Filter::Nearest => self.nearest(level,x,y,sampler),
+            Filter::Linear => self.linear(level,x,y,sampler),
+        }
+    }
+
+    // Samples at texture coordinates uv.x, uv.y (v up) on level 0, the
+    // magnification filter is used
+    pub fn sample(&self,sampler:&Sampler,uv:&Vector)->Vector{
+        self.sample_level(sampler,uv,0f32)
+    }
+
+    // LOD from the screen-space derivatives of uv, see FragmentInput::ddx
+    pub fn sample_grad(&self,sampler:&Sampler,uv:&Vector,ddx:&Vector,ddy:&Vector)->Vector{
+        self.sample_level(sampler,uv,self.lod(ddx,ddy))
+    }
+
+    // Samples with an explicit LOD, clamped to the mip chain. A NaN or
+    // infinite LOD, e.g. from degenerate derivatives, reads level 0.
+    pub fn sample_level(&self,sampler:&Sampler,uv:&Vector,lod:f32)->Vector{
+        let lod = if lod.is_finite() { lod } else { 0f32 };
+        let filter = if lod > 0f32 { sampler.min_filter } else { sampler.mag_filter };
+        let lod = lod.clamp(0f32, (self.levels.len() - 1) as f32);
+        match sampler.mipmap {
+            MipmapMode::None => self.filter(0,uv.x,uv.y,filter,sampler),
+            MipmapMode::Nearest => self.filter(lod.round() as usize,uv.x,uv.y,filter,sampler),
+            MipmapMode::Linear => {
+                let l0 = lod.floor() as usize;
+                let t = lod - l0 as f32;
+                if t == 0f32 {
+                    return self.filter(l0,uv.x,uv.y,filter,sampler);
+                }
+                Vector::lerp(&self.filter(l0,uv.x,uv.y,filter,sampler), &self.filter(l0 + 1,uv.x,uv.y,filter,sampler), t)
+            }
+        }
+    }
+
+    // Shorthands with repeat addressing
+    #[inline]
+    pub fn get_color_nearest(&self,x:f32,y:f32)->Vector{
+        self.sample(&Sampler::nearest(),&Vector::vec2(x,y))
+    }
+
+    pub fn get_color_linear(&self, x:f32, y:f32) ->Vector {
+        self.sample(&Sampler::linear(),&Vector::vec2(x,y))
+    }
+
+    // Nearest texel of the nearest mip level
+    pub fn get_color_nearest_mipmap(&self,x:f32,y:f32,ddx:&Vector,ddy:&Vector)->Vector{
+        self.sample_grad(&Sampler::nearest_mipmap(),&Vector::vec2(x,y),ddx,ddy)
+    }
+
+    // Bilinear in the two mip levels around the LOD, blended linearly
+    pub fn get_color_trilinear(&self,x:f32,y:f32,ddx:&Vector,ddy:&Vector)->Vector{
+        self.sample_grad(&Sampler::trilinear(),&Vector::vec2(x,y),ddx,ddy)
+    }
+
+    // Trilinear filtering with an explicit LOD, clamped to the mip chain
+    pub fn get_color_lod(&self,x:f32,y:f32,lod:f32)->Vector{
+        self.sample_level(&Sampler::trilinear(),&Vector::vec2(x,y),lod)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::texture::Texture;
+    use crate::sampler::{Sampler, AddressMode};
+    use crate::vector::Vector;
+    use image::{DynamicImage, RgbaImage, Rgba};
+
+    fn checker(w: u32, h: u32) -> Texture {
+        let image = RgbaImage::from_fn(w, h, |x, y| if (x + y) % 2 == 0 { Rgba([255, 255, 255, 255]) } else { Rgba([0, 0, 0, 255]) });
+        Texture::new(DynamicImage::ImageRgba8(image))
+    }
+
+    #[test]
+    fn mip_chain() {
+        let tex = checker(8, 8);
+        assert_eq!(4, tex.levels());
+        let sizes: Vec<_> = tex.levels.iter().map(|l| l.dimensions()).collect();
+        assert_eq!(vec![(8, 8), (4, 4), (2, 2), (1, 1)], sizes);
+        // A checkerboard averages to gray from level 1 on
+        assert!(tex.levels[1..].iter().all(|l| l.pixels().all(|p| p.0 == [0.5, 0.5, 0.5, 1.0])));
+
+        let sizes: Vec<_> = checker(5, 2).levels.iter().map(|l| l.dimensions()).collect();
+        assert_eq!(vec![(5, 2), (2, 1), (1, 1)], sizes);
+    }
+
+    #[test]
+    #[should_panic(expected = "a texture can't be empty")]
+    fn empty() {
+        Texture::from_rgba(0, 4, Vec::new());
+    }
+
+    #[test]
+    fn lod_from_derivatives() {
+        let tex = checker(64, 32);
+        // One texel per pixel
+        assert_eq!(0.0, tex.lod(&Vector::vec2(1.0 / 64.0, 0.0), &Vector::vec2(0.0, 1.0 / 32.0)));
+        // The longer axis of the footprint wins
+        assert_eq!(2.0, tex.lod(&Vector::vec2(4.0 / 64.0, 0.0), &Vector::vec2(0.0, 1.0 / 32.0)));
+        assert_eq!(-1.0, tex.lod(&Vector::vec2(0.5 / 64.0, 0.0), &Vector::vec2(0.0, 0.5 / 32.0)));
+    }
+
+    #[test]
+    fn trilinear_blends_levels() {
+        let tex = checker(4, 4);
+        let (u, v) = (0.125, 0.875);
+        let white = Vector::new(1.0, 1.0, 1.0, 1.0);
+        let gray = tex.get_color_lod(u, v, 1.0);
+        assert_eq!(white, tex.get_color_lod(u, v, 0.0));
+        assert_eq!(white, tex.get_color_lod(u, v, -3.0));
+        assert_eq!(Vector::lerp(&white, &gray, 0.25), tex.get_color_lod(u, v, 0.25));
+        assert_eq!(gray, tex.get_color_lod(u, v, 10.0));
+    }
+
+    #[test]
+    fn non_finite_lod() {
+        let tex = checker(4, 4);
+        let uv = Vector::vec2(0.125, 0.875);
+        for &lod in [f32::NAN, f32::INFINITY, -f32::INFINITY].iter() {
+            assert_eq!(tex.sample_level(&Sampler::trilinear(), &uv, 0.0), tex.sample_level(&Sampler::trilinear(), &uv, lod));
+        }
+        let nan = Vector::vec2(f32::NAN, f32::NAN);
+        assert_eq!(tex.sample_level(&Sampler::trilinear(), &uv, 0.0), tex.sample_grad(&Sampler::trilinear(), &uv, &nan, &nan));
+    }
+
+    #[test]
+    fn texel_centers() {
+        let tex = checker(4, 4);
+        let white = Vector::new(1.0, 1.0, 1.0, 1.0);
+        let gray = Vector::new(0.5, 0.5, 0.5, 1.0);
+        assert_eq!(white, tex.sample(&Sampler::linear(), &Vector::vec2(0.125, 0.875)));
+        assert_eq!(gray, tex.sample(&Sampler::linear(), &Vector::vec2(0.25, 0.875)));
+        // Repeat blends the last column with the first one
+        assert_eq!(gray, tex.sample(&Sampler::linear(), &Vector::vec2(0.0, 0.875)));
+        assert_eq!(tex.sample(&Sampler::nearest(), &Vector::vec2(0.1, 0.9)), tex.sample(&Sampler::nearest(), &Vector::vec2(-0.9, 1.9)));
+    }
+
+    #[test]
+    fn border_and_clamp() {
+        let tex = checker(4, 4);
+        let border = Vector::new(0.0, 1.0, 0.0, 1.0);
+        let sampler = Sampler::linear().with_address(AddressMode::ClampToBorder, AddressMode::ClampToEdge).with_border(border.clone());
+        assert_eq!(border, tex.sample(&sampler, &Vector::vec2(-0.5, 0.875)));
+        // Half way between the first texel and the border
+        let c = tex.sample(&sampler, &Vector::vec2(0.0, 0.875));
+        assert_eq!(Vector::new(0.5, 1.0, 0.5, 1.0), c);
+        // Clamped in v, the first row is read far above the texture
+        assert_eq!(tex.sample(&sampler, &Vector::vec2(0.125, 0.875)), tex.sample(&sampler, &Vector::vec2(0.125, 7.0)));
+    }
+
+    #[test]
+    fn non_finite_uv() {
+        let tex = checker(4, 4);
+        let modes = [AddressMode::Repeat, AddressMode::MirroredRepeat, AddressMode::ClampToEdge, AddressMode::ClampToBorder, AddressMode::MirrorOnce];
+        let values = [f32::INFINITY, -f32::INFINITY, f32::NAN, 1e30, -1e30];
+        for &mode in modes.iter() {
+            for sampler in [Sampler::nearest(), Sampler::linear()].iter() {
+                let sampler = sampler.clone().with_address(mode, mode);
+                for &u in values.iter() {
+                    for &v in values.iter().chain([0.5].iter()) {
+                        let c = tex.sample(&sampler, &Vector::vec2(u, v));
+                        assert!([c.x, c.y, c.z, c.w].iter().all(|c| (0.0..=1.0).contains(c)), "{:?} at ({}, {})", mode, u, v);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use crate::vector::Vector;
+use crate::framebuffer::Framebuffer;
+use crate::format::ColorFormat;
+use crate::state::{DepthState, StencilFaceState, StencilOp};
+use std::sync::Mutex;
+use std::thread;
+
+pub(crate) const TILE_SIZE: usize = 64;
+
+// A rectangle of the framebuffer, every buffer is held as one mutable slice
+// per row, so tiles of the same framebuffer never alias and can be shaded on
+// different threads
+pub(crate) struct Tile<'a> {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    // Samples per pixel, every buffer holds them next to each other
+    pub samples: usize,
+
+    // Per color attachment, RGBA f32. The samples when multisampled, they
+    // are resolved afterwards.
+    colors: Vec<Vec<&'a mut [f32]>>,
+    formats: Vec<ColorFormat>,
+    depth: Vec<&'a mut [f32]>,
+    stencil: Vec<&'a mut [u8]>,
+}
+
+// Splits a row-major buffer of `channels` elements per pixel into the row
+// slices of each tile, tiles are ordered row by row
+fn split_rows<T>(buf: &mut [T], width: usize, channels: usize, tile_size: usize) -> Vec<Vec<&mut [T]>> {
+    let tiles_x = width.div_ceil(tile_size);
+    let mut tiles: Vec<Vec<&mut [T]>> = Vec::new();
+
+    for (y, row) in buf.chunks_mut(width * channels).enumerate() {
+        let ty = y / tile_size;
+        if tiles.len() < (ty + 1) * tiles_x {
+            tiles.resize_with((ty + 1) * tiles_x, Vec::new);
+        }
+        for (tx, part) in row.chunks_mut(tile_size * channels).enumerate() {
+            tiles[ty * tiles_x + tx].push(part);
+        }
+    }
+    tiles
+}
+
+impl<'a> Tile<'a> {
+    pub fn split(fb: &'a mut Framebuffer, tile_size: usize) -> Vec<Tile<'a>> {
+        let (width, height, samples) = (fb.width(), fb.height(), fb.samples());
+        let tiles_x = width.div_ceil(tile_size);
+        let depth = split_rows(&mut fb.depth, width, samples, tile_size);
+        let stencil = split_rows(&mut fb.stencil, width, samples, tile_size);
+        let formats = fb.formats.clone();
+        let colors = if samples > 1 { &mut fb.sample_colors } else { &mut fb.colors };
+        let mut colors: Vec<_> = colors.iter_mut().map(|c| split_rows(c, width, 4 * samples, tile_size).into_iter()).collect();
+
+        depth.into_iter().zip(stencil).enumerate().map(|(i, (depth, stencil))| {
+            let (x, y) = ((i % tiles_x) * tile_size, (i / tiles_x) * tile_size);
+            Tile {
+                x,
+                y,
+                width: tile_size.min(width - x),
+                height: tile_size.min(height - y),
+                samples,
+                colors: colors.iter_mut().map(|c| c.next().unwrap()).collect(),
+                formats: formats.clone(),
+                depth,
+                stencil,
+            }
+        }).collect()
+    }
+
+    pub fn color_attachments(&self) -> usize {
+        self.colors.len()
+    }
+
+    // (x0, y0, x1, y1), exclusive end
+    pub fn rect(&self) -> (usize, usize, usize, usize) {
+        (self.x, self.y, self.x + self.width, self.y + self.height)
+    }
+
+    // Position of sample `s` of pixel column `x` in a row
+    #[inline]
+    fn index(&self, x: usize, s: usize) -> usize {
+        (x - self.x) * self.samples + s
+    }
+
+    // Depth test of sample `s` without writing
+    #[inline]
+    pub fn depth_passes(&self, x: usize, y: usize, s: usize, depth: f32, state: &DepthState) -> bool {
+        state.func.test(depth, self.depth[y - self.y][self.index(x, s)])
+    }
+
+    // Depth test of sample `s`, the depth is written when it passes and
+    // writes are enabled
+    #[inline]
+    pub fn depth_test(&mut self, x: usize, y: usize, s: usize, depth: f32, state: &DepthState) -> bool {
+        if !self.depth_passes(x, y, s, depth, state) {
+            return false;
+        }
+        if state.write {
+            let i = self.index(x, s);
+            self.depth[y - self.y][i] = depth;
+        }
+        true
+    }
+
+    #[inline]
+    pub fn stencil_test(&self, x: usize, y: usize, s: usize, face: &StencilFaceState) -> bool {
+        face.test(self.stencil[y - self.y][self.index(x, s)])
+    }
+
+    #[inline]
+    pub fn stencil_update(&mut self, x: usize, y: usize, s: usize, face: &StencilFaceState, op: StencilOp) {
+        let i = self.index(x, s);
+        let v = &mut self.stencil[y - self.y][i];
+        *v = face.update(*v, op);
+    }
+
+    #[inline]
+    pub fn get_color(&self, attachment: usize, x: usize, y: usize, s: usize) -> Vector {
+        let pos = self.index(x, s) * 4;
+        let cb = &self.colors[attachment][y - self.y];
+        Vector::new(cb[pos], cb[pos + 1], cb[pos + 2], cb[pos + 3])
+    }
+
+    #[inline]
+    pub fn set_color(&mut self, attachment: usize, x: usize, y: usize, s: usize, color: &Vector) {
+        let pos = self.index(x, s) * 4;
+        let c = self.formats[attachment].store(color);
+        self.colors[attachment][y - self.y][pos..pos + 4].copy_from_slice(&[c.x, c.y, c.z, c.w]);
+    }
+}
+
+// Sorts primitives into the tiles their screen bounding box overlaps,
+// keeping submission order inside each bin. `bounds` gives (x0, y0, x1, y1)
+// in pixels clamped to the framebuffer, exclusive end.
+pub(crate) fn bin<I>(bounds: I, width: usize, height: usize, tile_size: usize) -> Vec<Vec<usize>>
+    where I: Iterator<Item = (usize, usize, usize, usize)>
+{
+    let tiles_x = width.div_ceil(tile_size);
+    let tiles_y = height.div_ceil(tile_size);
+    let mut bins = vec![Vec::new(); tiles_x * tiles_y];
+
+    for (i, (x0, y0, x1, y1)) in bounds.enumerate() {
+        if x0 >= x1 || y0 >= y1 {
+            continue;
+        }
+        for ty in y0 / tile_size..=(y1 - 1) / tile_size {
+            for tx in x0 / tile_size..=(x1 - 1) / tile_size {
+                bins[ty * tiles_x + tx].push(i);
+            }
+        }
+    }
+    bins
+}
+
+// Runs `f` on every tile with at most `threads` workers
+pub(crate) fn run_parallel<F>(tiles: Vec<Tile>, bins: Vec<Vec<usize>>, threads: usize, f: F)
+    where F: Fn(&mut Tile, &[usize]) + Sync
+{
+    let jobs = Mutex::new(tiles.into_iter().zip(bins).filter(|(_, bin)| !bin.is_empty()));
+    let workers = threads.max(1);
+
+    thread::scope(|s| {
+        for _ in 0..workers {
+            s.spawn(|| loop {
+                let job = jobs.lock().unwrap().next();
+                match job {
+                    Some((mut tile, bin)) => f(&mut tile, &bin),
+                    None => break,
+                }
+            });
+        }
+    });
+}
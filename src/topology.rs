@@ -0,0 +1,67 @@
+// How the vertex stream of a draw call, or its index buffer, is read as
+// primitives
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PrimitiveTopology {
+    PointList,
+    // 0 1, 2 3, ...
+    LineList,
+    // 0 1, 1 2, 2 3, ...
+    LineStrip,
+    // 0 1 2, 3 4 5, ...
+    #[default]
+    TriangleList,
+    // 0 1 2, 2 1 3, 2 3 4, ... every other triangle is flipped to keep the winding
+    TriangleStrip,
+    // 0 1 2, 0 2 3, 0 3 4, ...
+    TriangleFan,
+}
+
+impl PrimitiveTopology {
+    // Calls `f` with the positions in the stream of the vertices of every
+    // primitive built from `count` vertices, incomplete primitives are dropped
+    pub(crate) fn assemble<F>(self, count: usize, mut f: F)
+        where F: FnMut(&[usize])
+    {
+        match self {
+            PrimitiveTopology::PointList => (0..count).for_each(|i| f(&[i])),
+            PrimitiveTopology::LineList => (0..count / 2).for_each(|i| f(&[i * 2, i * 2 + 1])),
+            PrimitiveTopology::LineStrip => (1..count).for_each(|i| f(&[i - 1, i])),
+            PrimitiveTopology::TriangleList => (0..count / 3).for_each(|i| f(&[i * 3, i * 3 + 1, i * 3 + 2])),
+            PrimitiveTopology::TriangleStrip => (2..count).for_each(|i| {
+                if i % 2 == 0 {
+                    f(&[i - 2, i - 1, i])
+                } else {
+                    f(&[i - 1, i - 2, i])
+                }
+            }),
+            PrimitiveTopology::TriangleFan => (2..count).for_each(|i| f(&[0, i - 1, i])),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::topology::PrimitiveTopology;
+
+    fn assemble(topology: PrimitiveTopology, count: usize) -> Vec<Vec<usize>> {
+        let mut out = vec![];
+        topology.assemble(count, |p| out.push(p.to_vec()));
+        out
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(assemble(PrimitiveTopology::PointList, 2), vec![vec![0], vec![1]]);
+        assert_eq!(assemble(PrimitiveTopology::LineList, 5), vec![vec![0, 1], vec![2, 3]]);
+        assert_eq!(assemble(PrimitiveTopology::TriangleList, 7), vec![vec![0, 1, 2], vec![3, 4, 5]]);
+        assert!(assemble(PrimitiveTopology::TriangleList, 2).is_empty());
+    }
+
+    #[test]
+    fn strips_and_fans() {
+        assert_eq!(assemble(PrimitiveTopology::LineStrip, 3), vec![vec![0, 1], vec![1, 2]]);
+        assert!(assemble(PrimitiveTopology::LineStrip, 1).is_empty());
+        assert_eq!(assemble(PrimitiveTopology::TriangleStrip, 5), vec![vec![0, 1, 2], vec![2, 1, 3], vec![2, 3, 4]]);
+        assert_eq!(assemble(PrimitiveTopology::TriangleFan, 5), vec![vec![0, 1, 2], vec![0, 2, 3], vec![0, 3, 4]]);
+    }
+}
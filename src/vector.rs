@@ -10,10 +10,7 @@ pub struct Vector {
     pub w: f32,
 }
 
-#[derive(Debug, PartialEq, Clone)]
-pub struct IVector {}
-
-impl<'a, 'b> Add<&'a Vector> for &'b Vector {
+impl<'a> Add<&'a Vector> for &Vector {
     type Output = Vector;
 
     #[inline]
@@ -27,7 +24,7 @@ impl<'a, 'b> Add<&'a Vector> for &'b Vector {
     }
 }
 
-impl<'a, 'b> Sub<&'a Vector> for &'a Vector {
+impl<'a> Sub<&'a Vector> for &'a Vector {
     type Output = Vector;
 
     #[inline]
@@ -41,7 +38,7 @@ impl<'a, 'b> Sub<&'a Vector> for &'a Vector {
     }
 }
 
-impl<'a, 'b> Mul<&'a Vector> for &'b Vector {
+impl<'a> Mul<&'a Vector> for &Vector {
     type Output = Vector;
 
     #[inline]
@@ -1,10 +1,70 @@
 use super::Vector;
+use soft3d_derive::VertexAttribute;
 
-pub trait VertexAttribute {
+// Attributes are interpolated smooth (perspective correct) unless the type
+// overrides perspective_scale and with_flat:
+// - noperspective fields, linear in screen space, are left alone by
+//   perspective_scale
+// - flat fields are copied from the provoking vertex by with_flat, the last
+//   vertex of the primitive as in OpenGL
+pub trait VertexAttribute: Sized {
     fn lerp(a: &Self, b: &Self, t: f32) -> Self;
     fn scale(&self, s: f32) -> Self;
+
+    // Scales the smooth fields only. The pipeline interpolates them divided
+    // by w across the screen and multiplies back afterwards.
+    fn perspective_scale(&self, s: f32) -> Self {
+        self.scale(s)
+    }
+
+    // `self` with its flat fields taken from `provoking`
+    fn with_flat(self, _provoking: &Self) -> Self {
+        self
+    }
+}
+
+// Vector, f32 and arrays of attributes are smooth, qualify them as fields
+// of a derived struct, see soft3d_derive
+impl VertexAttribute for Vector {
+    fn lerp(a: &Vector, b: &Vector, t: f32) -> Vector {
+        Vector::lerp(a, b, t)
+    }
+
+    fn scale(&self, s: f32) -> Vector {
+        Vector::scale(self, s)
+    }
+}
+
+impl VertexAttribute for f32 {
+    fn lerp(a: &f32, b: &f32, t: f32) -> f32 {
+        a + (b - a) * t
+    }
+
+    fn scale(&self, s: f32) -> f32 {
+        self * s
+    }
 }
 
+impl<T: VertexAttribute, const N: usize> VertexAttribute for [T; N] {
+    fn lerp(a: &Self, b: &Self, t: f32) -> Self {
+        std::array::from_fn(|i| T::lerp(&a[i], &b[i], t))
+    }
+
+    fn scale(&self, s: f32) -> Self {
+        std::array::from_fn(|i| self[i].scale(s))
+    }
+
+    fn perspective_scale(&self, s: f32) -> Self {
+        std::array::from_fn(|i| self[i].perspective_scale(s))
+    }
+
+    fn with_flat(self, provoking: &Self) -> Self {
+        let mut provoking = provoking.iter();
+        self.map(|v| v.with_flat(provoking.next().unwrap()))
+    }
+}
+
+#[derive(VertexAttribute)]
 pub struct Vertex{
     pub pos:Vector,
     pub color:Vector,
@@ -12,22 +72,36 @@ pub struct Vertex{
     pub uv:Vector,
 }
 
-impl VertexAttribute for Vertex{
-    fn lerp(a:&Vertex,b:&Vertex,t:f32)->Vertex{
-        Vertex{
-            pos: Vector::lerp(&a.pos,&b.pos,t),
-            color: Vector::lerp(&a.color,&b.color,t),
-            normal: Vector::lerp(&a.normal,&b.normal,t),
-            uv: Vector::lerp(&a.uv,&b.uv,t),
-        }
+#[cfg(test)]
+mod test {
+    use crate::vector::Vector;
+    use crate::vertex::VertexAttribute;
+
+    // Flat fields only need Clone, integers included
+    #[derive(VertexAttribute, Debug, PartialEq)]
+    struct Weights(#[noperspective] [f32; 2], #[flat] u8);
+
+    #[derive(VertexAttribute, Debug, PartialEq)]
+    struct Varying {
+        color: Vector,
+        weights: Weights,
+        #[flat]
+        id: u32,
     }
 
-    fn scale(&self, s: f32) -> Self {
-        Vertex{
-            pos: self.pos.scale(s),
-            color: self.color.scale(s),
-            normal: self.normal.scale(s),
-            uv: self.uv.scale(s),
-        }
-    }
-}
\ No newline at end of file
+    fn varying(c: f32, w: [f32; 2], id: u32) -> Varying {
+        Varying { color: Vector::new(c, c, c, c), weights: Weights(w, id as u8 * 2), id }
+    }
+
+    #[test]
+    fn derive() {
+        let (a, b) = (varying(0.0, [0.0, 2.0], 1), varying(1.0, [4.0, 6.0], 3));
+        // Flat fields are copied from the first one until with_flat
+        assert_eq!(varying(0.25, [1.0, 3.0], 1), Varying::lerp(&a, &b, 0.25));
+        assert_eq!(varying(2.0, [8.0, 12.0], 3), b.scale(2.0));
+        // noperspective and flat fields, nested ones too, are not scaled
+        let Varying { color, weights, id } = b.perspective_scale(2.0);
+        assert_eq!((Vector::new(2.0, 2.0, 2.0, 2.0), Weights([4.0, 6.0], 6), 3), (color, weights, id));
+        assert_eq!(varying(0.0, [0.0, 2.0], 3), a.with_flat(&b));
+    }
+}
@@ -0,0 +1,1143 @@
+// Golden-image tests: fixed scenes are rendered offscreen and compared with the
+// reference images in tests/golden.
+//
+// Regenerate the references after an intended change with
+//     UPDATE_GOLDEN=1 cargo test --test golden
+// On mismatch the actual image and a diff image are written to
+// target/tmp/golden-diff.
+
+use soft3d_rs::{Vertex, VertexAttribute, Vector, Matrix, Renderer, ShaderRenderer, Shader, VSOutput, FragmentInput, FragmentOutput, Framebuffer, ColorFormat, DownsampleFilter, Texture, CubeTexture, Sampler, AddressMode, Filter, MipmapMode, BlendState, BlendFactor, BlendOp, DepthState, CompareFunc,
+                StencilState, StencilFaceState, StencilOp,
+                RasterizerState, CullMode, FrontFace, MultisampleState, Viewport, PrimitiveTopology, scene};
+use image::{DynamicImage, RgbImage, RgbaImage, Rgb, Rgba};
+use std::path::PathBuf;
+use std::fs;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 64;
+
+// Largest per-channel difference that still counts as the same pixel
+const TOLERANCE: u8 = 2;
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden").join(format!("{}.png", name))
+}
+
+fn diff_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("golden-diff")
+}
+
+fn assert_golden(name: &str, actual: &RgbImage) {
+    let path = golden_path(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        actual.save(&path).unwrap();
+        return;
+    }
+
+    let expected = image::open(&path)
+        .unwrap_or_else(|e| panic!("{}: {} (run with UPDATE_GOLDEN=1 to create it)", path.display(), e))
+        .to_rgb();
+    assert_eq!(expected.dimensions(), actual.dimensions(), "{}: size mismatch", name);
+
+    let mut bad = 0;
+    let diff = RgbImage::from_fn(actual.width(), actual.height(), |x, y| {
+        let e = expected.get_pixel(x, y);
+        let a = actual.get_pixel(x, y);
+        let over = e.0.iter().zip(a.0.iter()).any(|(e, a)| (*e as i32 - *a as i32).abs() > TOLERANCE as i32);
+        if over {
+            bad += 1;
+            Rgb([255, 0, 0])
+        } else {
+            // Dimmed reference so the failing pixels stand out
+            Rgb([e.0[0] / 4, e.0[1] / 4, e.0[2] / 4])
+        }
+    });
+
+    if bad > 0 {
+        let dir = diff_dir();
+        fs::create_dir_all(&dir).unwrap();
+        actual.save(dir.join(format!("{}.actual.png", name))).unwrap();
+        diff.save(dir.join(format!("{}.diff.png", name))).unwrap();
+        panic!("{}: {} pixels differ by more than {}, see {}", name, bad, TOLERANCE, dir.display());
+    }
+}
+
+fn vertex(pos: Vector, uv: Vector, color: Vector) -> Vertex {
+    Vertex { pos, uv, color, normal: Vector::zero() }
+}
+
+fn copy_vertex(v: &Vertex) -> Vertex {
+    vertex(v.pos.clone(), v.uv.clone(), v.color.clone())
+}
+
+fn red() -> Vector { Vector::new(1.0, 0.0, 0.0, 1.0) }
+fn green() -> Vector { Vector::new(0.0, 1.0, 0.0, 1.0) }
+fn blue() -> Vector { Vector::new(0.0, 0.0, 1.0, 1.0) }
+
+// 8x8 checkerboard with a colored corner so orientation is visible
+fn checker() -> Texture {
+    let image = RgbaImage::from_fn(8, 8, |x, y| {
+        if x < 2 && y < 2 {
+            Rgba([255, 0, 0, 255])
+        } else if (x + y) % 2 == 0 {
+            Rgba([255, 255, 255, 255])
+        } else {
+            Rgba([0, 0, 0, 255])
+        }
+    });
+    Texture::new(DynamicImage::ImageRgba8(image))
+}
+
+// Positions are already in clip space
+fn render_clip_space<FS>(vertices: &[Vertex], fs: FS) -> RgbImage
+    where FS: Fn(&FragmentInput<Vertex>) -> Vector
+{
+    let mut ren = Renderer::new(WIDTH, HEIGHT);
+    ren.set_vs(|v: &Vertex| VSOutput::new(v.pos.clone(), copy_vertex(v)));
+    ren.set_fs(fs);
+    ren.clear_color(0.2, 0.2, 0.2);
+    ren.clear();
+    ren.render(vertices);
+    ren.color_image()
+}
+
+fn vertex_color(f: &FragmentInput<Vertex>) -> Vector {
+    f.va.color.clone()
+}
+
+fn clip_scene(name: &str, positions: [Vector; 3]) {
+    let [p0, p1, p2] = positions;
+    let vertices = [
+        vertex(p0, Vector::zero(), red()),
+        vertex(p1, Vector::zero(), green()),
+        vertex(p2, Vector::zero(), blue()),
+    ];
+    assert_golden(name, &render_clip_space(&vertices, vertex_color));
+}
+
+#[test]
+fn clip_nx() {
+    clip_scene("clip_nx", [Vector::point(-2.5, -0.5, 0.0), Vector::point(0.5, -0.8, 0.0), Vector::point(0.3, 0.7, 0.0)]);
+}
+
+#[test]
+fn clip_x() {
+    clip_scene("clip_x", [Vector::point(-0.5, -0.8, 0.0), Vector::point(2.5, 0.2, 0.0), Vector::point(-0.3, 0.7, 0.0)]);
+}
+
+#[test]
+fn clip_ny() {
+    clip_scene("clip_ny", [Vector::point(-0.7, 0.5, 0.0), Vector::point(0.2, -2.5, 0.0), Vector::point(0.8, 0.6, 0.0)]);
+}
+
+#[test]
+fn clip_y() {
+    clip_scene("clip_y", [Vector::point(-0.7, -0.5, 0.0), Vector::point(0.8, -0.6, 0.0), Vector::point(0.1, 2.5, 0.0)]);
+}
+
+#[test]
+fn clip_nz() {
+    clip_scene("clip_nz", [Vector::point(-0.8, -0.8, -3.0), Vector::point(0.8, -0.8, 0.5), Vector::point(0.0, 0.8, 0.5)]);
+}
+
+#[test]
+fn clip_z() {
+    clip_scene("clip_z", [Vector::point(-0.8, -0.8, -0.5), Vector::point(0.8, -0.8, 3.0), Vector::point(0.0, 0.8, -0.5)]);
+}
+
+#[test]
+fn clip_all_planes() {
+    clip_scene("clip_all_planes", [Vector::point(-3.0, -3.0, 0.0), Vector::point(3.0, -3.0, 0.0), Vector::point(0.0, 3.0, 0.0)]);
+}
+
+#[test]
+fn back_face_culling() {
+    // Left triangle is counter-clockwise in NDC and kept, right one is
+    // clockwise and culled
+    let vertices = [
+        vertex(Vector::point(-0.9, -0.5, 0.0), Vector::zero(), red()),
+        vertex(Vector::point(-0.1, -0.5, 0.0), Vector::zero(), red()),
+        vertex(Vector::point(-0.5, 0.5, 0.0), Vector::zero(), red()),
+        vertex(Vector::point(0.1, -0.5, 0.0), Vector::zero(), blue()),
+        vertex(Vector::point(0.5, 0.5, 0.0), Vector::zero(), blue()),
+        vertex(Vector::point(0.9, -0.5, 0.0), Vector::zero(), blue()),
+    ];
+    assert_golden("back_face_culling", &render_clip_space(&vertices, vertex_color));
+}
+
+// Floor plane seen at a grazing angle, the checker squares have to shrink
+// towards the horizon
+#[test]
+fn perspective_correct() {
+    let tex = checker();
+    let floor = [
+        vertex(Vector::point(-4.0, -1.0, 2.0), Vector::vec2(0.0, 0.0), red()),
+        vertex(Vector::point(4.0, -1.0, 2.0), Vector::vec2(1.0, 0.0), red()),
+        vertex(Vector::point(4.0, -1.0, -20.0), Vector::vec2(1.0, 4.0), red()),
+        vertex(Vector::point(-4.0, -1.0, -20.0), Vector::vec2(0.0, 4.0), red()),
+    ];
+    let p = soft3d_rs::Matrix::perspective(std::f32::consts::PI * 0.5, 1.0, 0.1, 100.0);
+
+    let mut ren = Renderer::new(WIDTH, HEIGHT);
+    ren.set_vs(move |v: &Vertex| VSOutput::new(p.apply(&v.pos), copy_vertex(v)));
+    ren.set_fs(move |f: &FragmentInput<Vertex>| tex.get_color_nearest(f.va.uv.x, f.va.uv.y));
+    ren.clear_color(0.2, 0.2, 0.2);
+    ren.clear();
+    ren.render_with_index(&floor, &[0, 1, 2, 0, 2, 3]);
+    assert_golden("perspective_correct", &ren.color_image());
+}
+
+// Same floor with the checker repeated, trilinear filtering with the quad
+// derivatives fades it to gray towards the horizon instead of aliasing
+#[test]
+fn perspective_trilinear() {
+    let tex = checker();
+    let floor = [
+        vertex(Vector::point(-4.0, -1.0, 2.0), Vector::vec2(0.0, 0.0), red()),
+        vertex(Vector::point(4.0, -1.0, 2.0), Vector::vec2(4.0, 0.0), red()),
+        vertex(Vector::point(4.0, -1.0, -20.0), Vector::vec2(4.0, 11.0), red()),
+        vertex(Vector::point(-4.0, -1.0, -20.0), Vector::vec2(0.0, 11.0), red()),
+    ];
+    let p = soft3d_rs::Matrix::perspective(std::f32::consts::PI * 0.5, 1.0, 0.1, 100.0);
+
+    let mut ren = Renderer::new(WIDTH, HEIGHT);
+    ren.set_vs(move |v: &Vertex| VSOutput::new(p.apply(&v.pos), copy_vertex(v)));
+    ren.set_fs(move |f: &FragmentInput<Vertex>| tex.get_color_trilinear(f.va.uv.x, f.va.uv.y, &f.ddx.uv, &f.ddy.uv));
+    ren.clear_color(0.2, 0.2, 0.2);
+    ren.clear();
+    ren.render_with_index(&floor, &[0, 1, 2, 0, 2, 3]);
+    assert_golden("perspective_trilinear", &ren.color_image());
+}
+
+// The floor uv twice, smooth and noperspective, and a flat palette index
+#[derive(VertexAttribute)]
+struct Qualified {
+    pos: Vector,
+    uv: Vector,
+    #[noperspective]
+    screen_uv: Vector,
+    #[flat]
+    material: usize,
+}
+
+// The floor of perspective_correct cut by the side planes, smooth uv on the
+// left half and noperspective uv on the right, which bends the checker.
+// Each triangle is tinted by the material of its last vertex.
+#[test]
+fn interpolation_qualifiers() {
+    let tex = checker();
+    let corners = [(-4.0, -0.5, 0.0, 0.0), (4.0, -0.5, 1.0, 0.0), (4.0, -20.0, 1.0, 4.0), (-4.0, -20.0, 0.0, 4.0)];
+    let floor: Vec<Qualified> = corners.iter().enumerate().map(|(i, &(x, z, u, v))| Qualified {
+        pos: Vector::point(x, -1.0, z),
+        uv: Vector::vec2(u, v),
+        screen_uv: Vector::vec2(u, v),
+        material: i,
+    }).collect();
+    let palette = [Vector::zero(), Vector::zero(), Vector::new(1.0, 0.5, 0.5, 1.0), Vector::new(0.5, 0.5, 1.0, 1.0)];
+    let p = soft3d_rs::Matrix::perspective(std::f32::consts::PI * 0.5, 1.0, 0.1, 100.0);
+
+    let mut ren = Renderer::new(WIDTH, HEIGHT);
+    ren.set_vs(move |v: &Qualified| VSOutput::new(p.apply(&v.pos), Qualified { pos: v.pos.clone(), ..Qualified::lerp(v, v, 0.0) }));
+    ren.set_fs(move |f: &FragmentInput<Qualified>| {
+        let uv = if f.frag_coord.x < WIDTH as f32 / 2.0 { &f.va.uv } else { &f.va.screen_uv };
+        &tex.get_color_nearest(uv.x, uv.y) * &palette[f.va.material]
+    });
+    ren.clear_color(0.2, 0.2, 0.2);
+    ren.clear();
+    ren.render_with_index(&floor, &[0, 1, 2, 0, 2, 3]);
+    assert_golden("interpolation_qualifiers", &ren.color_image());
+}
+
+// On the full screen quad uv moves 1 / 64 per pixel in x and -1 / 64 in y,
+// on the edge pixels too where the quads are only partly covered
+#[test]
+fn quad_derivatives() {
+    let shaded = std::cell::Cell::new(0);
+    let img = render_clip_space(&texture_quad(), |f: &FragmentInput<Vertex>| {
+        let d = 1.0 / WIDTH as f32;
+        assert!((f.ddx.uv.x - d).abs() < 1e-5 && f.ddx.uv.y.abs() < 1e-5);
+        assert!(f.ddy.uv.x.abs() < 1e-5 && (f.ddy.uv.y + d).abs() < 1e-5);
+        shaded.set(shaded.get() + 1);
+        red()
+    });
+    assert_eq!(WIDTH * HEIGHT, shaded.get());
+    assert_eq!(Rgb([255, 0, 0]), *img.get_pixel(0, 0));
+}
+
+fn texture_quad() -> Vec<Vertex> {
+    let quad = [
+        (Vector::point(-1.0, -1.0, 0.0), Vector::vec2(0.0, 0.0)),
+        (Vector::point(1.0, -1.0, 0.0), Vector::vec2(1.0, 0.0)),
+        (Vector::point(1.0, 1.0, 0.0), Vector::vec2(1.0, 1.0)),
+        (Vector::point(-1.0, 1.0, 0.0), Vector::vec2(0.0, 1.0)),
+    ];
+    [0, 1, 2, 0, 2, 3].iter()
+        .map(|&i| vertex(quad[i].0.clone(), quad[i].1.clone(), red()))
+        .collect()
+}
+
+#[test]
+fn texture_nearest() {
+    let tex = checker();
+    let img = render_clip_space(&texture_quad(), move |f: &FragmentInput<Vertex>| tex.get_color_nearest(f.va.uv.x, f.va.uv.y));
+    assert_golden("texture_nearest", &img);
+}
+
+#[test]
+fn texture_linear() {
+    let tex = checker();
+    let img = render_clip_space(&texture_quad(), move |f: &FragmentInput<Vertex>| tex.get_color_linear(f.va.uv.x, f.va.uv.y));
+    assert_golden("texture_linear", &img);
+}
+
+// Four bands minifying the checker more and more, left half trilinear and
+// right half nearest-mipmap
+#[test]
+fn texture_mipmap() {
+    let tex = checker();
+    let mut vertices = vec![];
+    for (i, &repeat) in [1.0f32, 12.0, 24.0, 48.0].iter().enumerate() {
+        let (y0, y1) = (1.0 - i as f32 * 0.5, 0.5 - i as f32 * 0.5);
+        let (v0, v1) = (1.0, 1.0 - repeat * 0.25);
+        let p = |x: f32, y: f32, u: f32, v: f32| vertex(Vector::point(x, y, 0.0), Vector::vec2(u, v), red());
+        vertices.extend(vec![
+            p(-1.0, y1, 0.0, v1), p(1.0, y1, repeat, v1), p(1.0, y0, repeat, v0),
+            p(-1.0, y1, 0.0, v1), p(1.0, y0, repeat, v0), p(-1.0, y0, 0.0, v0),
+        ]);
+    }
+    let img = render_clip_space(&vertices, move |f: &FragmentInput<Vertex>| {
+        if f.va.pos.x < 0.0 {
+            tex.get_color_trilinear(f.va.uv.x, f.va.uv.y, &f.ddx.uv, &f.ddy.uv)
+        } else {
+            tex.get_color_nearest_mipmap(f.va.uv.x, f.va.uv.y, &f.ddx.uv, &f.ddy.uv)
+        }
+    });
+    assert_golden("texture_mipmap", &img);
+}
+
+// One cell per sampler, every cell maps uv from -1 to 2. Top row: repeat,
+// mirrored repeat and clamp to edge, bottom row: clamp to border (green),
+// mirror once, and mirrored in u with a nearest filter and border in v.
+#[test]
+fn texture_address_modes() {
+    let tex = checker();
+    let linear = |mode| Sampler::new(mode, Filter::Linear, MipmapMode::None).with_border(green());
+    let samplers = [
+        linear(AddressMode::Repeat),
+        linear(AddressMode::MirroredRepeat),
+        linear(AddressMode::ClampToEdge),
+        linear(AddressMode::ClampToBorder),
+        linear(AddressMode::MirrorOnce),
+        Sampler::nearest().with_address(AddressMode::MirroredRepeat, AddressMode::ClampToBorder).with_border(blue()),
+    ];
+    let img = render_clip_space(&texture_quad(), move |f: &FragmentInput<Vertex>| {
+        let (sx, sy) = ((f.va.uv.x * 3.0).min(2.999), ((1.0 - f.va.uv.y) * 2.0).min(1.999));
+        let (cx, cy) = (sx.floor(), sy.floor());
+        let uv = Vector::vec2((sx - cx) * 3.0 - 1.0, 2.0 - (sy - cy) * 3.0);
+        tex.sample(&samplers[cy as usize * 3 + cx as usize], &uv)
+    });
+    assert_golden("texture_address_modes", &img);
+}
+
+#[test]
+fn demo_cube() {
+    let tex = checker();
+    let (data, indices) = scene::cube();
+    let pv = scene::orbit_camera(0.5, 1.0);
+
+    let mut ren = Renderer::new(WIDTH, HEIGHT);
+    ren.set_vs(move |v: &Vertex| VSOutput::new(pv.apply(&v.pos), copy_vertex(v)));
+    ren.set_fs(move |f: &FragmentInput<Vertex>| tex.get_color_linear(f.va.uv.x, f.va.uv.y));
+    ren.clear_color(0.5, 0.8, 1.0);
+    ren.clear();
+    ren.render_with_index(&data, &indices);
+    assert_golden("demo_cube", &ren.color_image());
+}
+
+// Cube texture with one color per face and a texel grid on each
+fn sky() -> CubeTexture {
+    let colors = [[200, 60, 60], [60, 200, 60], [120, 160, 230], [90, 70, 40], [200, 200, 60], [60, 200, 200]];
+    let face = |c: [u8; 3]| DynamicImage::ImageRgba8(RgbaImage::from_fn(16, 16, |x, y| {
+        if x % 4 == 0 || y % 4 == 0 {
+            Rgba([c[0] / 2, c[1] / 2, c[2] / 2, 255])
+        } else {
+            Rgba([c[0], c[1], c[2], 255])
+        }
+    }));
+    CubeTexture::from_faces([face(colors[0]), face(colors[1]), face(colors[2]), face(colors[3]), face(colors[4]), face(colors[5])])
+}
+
+// The demo cube mirrors the sky along its object space position and the
+// sky is drawn afterwards around it
+#[test]
+fn skybox() {
+    let (data, indices) = scene::cube();
+    let pv = scene::orbit_camera(0.5, 1.0);
+    let sky = std::sync::Arc::new(sky());
+    let cube_sky = sky.clone();
+
+    let mut ren = Renderer::new(WIDTH, HEIGHT);
+    ren.set_vs(move |v: &Vertex| VSOutput::new(pv.apply(&v.pos), copy_vertex(v)));
+    ren.set_fs(move |f: &FragmentInput<Vertex>| cube_sky.sample(&Sampler::linear(), &f.va.pos).scale(0.6));
+    ren.clear();
+    ren.render_with_index(&data, &indices);
+    ren.draw_skybox(&sky, &Sampler::linear(), &scene::orbit_camera(0.5, 1.0));
+    assert_golden("skybox", &ren.color_image());
+}
+
+// The cube is drawn into a 32x32 framebuffer with two color attachments,
+// textured and its uv as color, then the attachments are shown side by side
+#[test]
+fn render_to_texture() {
+    let (data, indices) = scene::cube();
+    let pv = scene::orbit_camera(0.5, 1.0);
+    let tex = checker();
+
+    let mut ren = Renderer::new(WIDTH, HEIGHT);
+    ren.set_vs(move |v: &Vertex| VSOutput::new(pv.apply(&v.pos), copy_vertex(v)));
+    ren.set_fs(move |f: &FragmentInput<Vertex>| [tex.get_color_nearest(f.va.uv.x, f.va.uv.y), Vector::new(f.va.uv.x, f.va.uv.y, 0.0, 1.0)]);
+    ren.bind_framebuffer(Framebuffer::new(32, 32, 2));
+    ren.clear_color(0.5, 0.8, 1.0);
+    ren.clear();
+    ren.render_with_index(&data, &indices);
+    let fb = ren.unbind_framebuffer().unwrap();
+
+    let sampler = Sampler::nearest();
+    let depth = fb.depth_texture();
+    assert_eq!(fb.depth_buffer()[0], depth.sample(&sampler, &Vector::vec2(0.0, 0.99)).x);
+    assert_eq!(1.0, fb.depth_buffer()[0]);
+    assert!(depth.sample(&sampler, &Vector::vec2(0.5, 0.5)).x < 1.0);
+
+    let (color, attr) = (fb.color_texture(0), fb.color_texture(1));
+    let mut ren = Renderer::new(WIDTH, HEIGHT);
+    ren.set_vs(|v: &Vertex| VSOutput::new(v.pos.clone(), copy_vertex(v)));
+    ren.set_fs(move |f: &FragmentInput<Vertex>| {
+        let (u, v) = (f.va.uv.x * 2.0, f.va.uv.y);
+        if u < 1.0 {
+            color.sample(&sampler, &Vector::vec2(u, v))
+        } else {
+            attr.sample(&sampler, &Vector::vec2(u - 1.0, v))
+        }
+    });
+    ren.clear();
+    ren.render(&texture_quad());
+    assert_golden("render_to_texture", &ren.color_image());
+}
+
+// Three additive passes of 0.8 go past 1 and are scaled back in a second
+// pass, only a float target keeps the sum
+#[test]
+fn hdr_accumulation() {
+    let accumulate = |format: ColorFormat| {
+        let mut ren = Renderer::new(8, 8);
+        ren.set_vs(|v: &Vertex| VSOutput::new(v.pos.clone(), copy_vertex(v)));
+        ren.set_fs(|_: &FragmentInput<Vertex>| Vector::new(0.8, 0.4, 0.1, 1.0));
+        ren.bind_framebuffer(Framebuffer::with_formats(8, 8, &[format]));
+        ren.clear();
+        ren.set_depth_state(DepthState::disabled());
+        ren.set_blend_state(BlendState::new(BlendFactor::One, BlendFactor::One, BlendOp::Add));
+        for _ in 0..3 {
+            ren.render(&texture_quad());
+        }
+        let tex = ren.unbind_framebuffer().unwrap().color_texture(0);
+
+        let mut ren = Renderer::new(8, 8);
+        ren.set_vs(|v: &Vertex| VSOutput::new(v.pos.clone(), copy_vertex(v)));
+        ren.set_fs(move |f: &FragmentInput<Vertex>| tex.sample(&Sampler::nearest(), &f.va.uv).scale(0.25));
+        ren.clear();
+        ren.render(&texture_quad());
+        ren.color_image().get_pixel(4, 4).0
+    };
+    assert_eq!([153, 76, 19], accumulate(ColorFormat::Rgba32F));
+    assert_eq!([153, 76, 19], accumulate(ColorFormat::Rgba16F));
+    assert_eq!([63, 63, 18], accumulate(ColorFormat::Rgba8));
+}
+
+#[test]
+fn color_formats() {
+    let mut ren = Renderer::new(4, 4);
+    ren.set_vs(|v: &Vertex| VSOutput::new(v.pos.clone(), copy_vertex(v)));
+    ren.set_fs(vertex_color);
+    ren.clear_color_alpha(0.5, 0.25, 2.0, 0.5);
+
+    ren.clear();
+    assert_eq!([127, 63, 255], ren.color_image().get_pixel(0, 0).0);
+    ren.set_color_format(ColorFormat::Rgba8);
+    ren.clear();
+    assert_eq!([127, 63, 255, 127], ren.color_image_rgba().get_pixel(0, 0).0);
+    ren.set_color_format(ColorFormat::Srgb8Alpha8);
+    ren.clear();
+    assert_eq!([188, 137, 255, 128], ren.color_image_rgba().get_pixel(0, 0).0);
+
+    let mut fb = Framebuffer::with_formats(4, 4, &[ColorFormat::Rgba32F, ColorFormat::R32F]);
+    ren.bind_framebuffer(fb);
+    ren.clear();
+    fb = ren.unbind_framebuffer().unwrap();
+    assert_eq!(&[0.5, 0.25, 2.0, 0.5], &fb.color_buffer(0)[..4]);
+    assert_eq!(&[0.5, 0.0, 0.0, 1.0], &fb.color_buffer(1)[..4]);
+    assert_eq!([128, 64, 255], fb.color_image(0).get_pixel(0, 0).0);
+}
+
+// The demo cube with 4x MSAA, the parallel path has to resolve the same
+#[test]
+fn msaa() {
+    let (data, indices) = scene::cube();
+    let pv = scene::orbit_camera(0.5, 1.0);
+    let render = |parallel: bool| {
+        let tex = checker();
+        let pv = pv.clone();
+        let mut ren = Renderer::new(WIDTH, HEIGHT);
+        ren.set_vs(move |v: &Vertex| VSOutput::new(pv.apply(&v.pos), copy_vertex(v)));
+        ren.set_fs(move |f: &FragmentInput<Vertex>| tex.get_color_linear(f.va.uv.x, f.va.uv.y));
+        ren.set_sample_count(4);
+        ren.clear_color(0.5, 0.8, 1.0);
+        ren.clear();
+        if parallel {
+            ren.render_with_index_parallel(&data, &indices);
+        } else {
+            ren.render_with_index(&data, &indices);
+        }
+        ren.color_image()
+    };
+    let single = render(false);
+    assert_golden("msaa", &single);
+    assert!(single.into_raw() == render(true).into_raw());
+}
+
+// The demo cube rasterized at 4x the size and shrunk with each filter
+#[test]
+fn supersampling() {
+    let (data, indices) = scene::cube();
+    let pv = scene::orbit_camera(0.5, 1.0);
+    for &(name, filter) in &[("ssaa_box", DownsampleFilter::Box), ("ssaa_tent", DownsampleFilter::Tent), ("ssaa_lanczos", DownsampleFilter::Lanczos)] {
+        let tex = checker();
+        let pv = pv.clone();
+        let mut ren = Renderer::new(WIDTH, HEIGHT);
+        ren.set_vs(move |v: &Vertex| VSOutput::new(pv.apply(&v.pos), copy_vertex(v)));
+        ren.set_fs(move |f: &FragmentInput<Vertex>| tex.get_color_trilinear(f.va.uv.x, f.va.uv.y, &f.ddx.uv, &f.ddy.uv));
+        ren.set_supersampling(4, filter);
+        ren.clear_color(0.5, 0.8, 1.0);
+        ren.clear();
+        ren.render_with_index_parallel(&data, &indices);
+        ren.get_color_buffer(|buf| assert_eq!(WIDTH * HEIGHT * 3, buf.len()));
+        assert_eq!((WIDTH as u32, HEIGHT as u32), ren.depth_image().dimensions());
+        assert_golden(name, &ren.color_image());
+    }
+}
+
+// A red triangle with alpha fading from 1 on the left to 0 on the right in
+// front of a green quad. With alpha to coverage the red covers fewer of the
+// 8 samples towards the right, and the quad drawn afterwards still fails the
+// depth test only on the samples the triangle wrote.
+#[test]
+fn alpha_to_coverage() {
+    let front = [
+        vertex(Vector::point(-0.9, -0.8, 0.2), Vector::vec2(0.0, 0.0), red()),
+        vertex(Vector::point(0.9, -0.8, 0.2), Vector::vec2(1.0, 0.0), red()),
+        vertex(Vector::point(0.0, 0.9, 0.2), Vector::vec2(0.5, 1.0), red()),
+    ];
+    let back = texture_quad().into_iter()
+        .map(|v| vertex(Vector::point(v.pos.x, v.pos.y, 0.5), v.uv, green()))
+        .collect::<Vec<_>>();
+
+    let mut ren = Renderer::new(WIDTH, HEIGHT);
+    ren.set_vs(|v: &Vertex| VSOutput::new(v.pos.clone(), copy_vertex(v)));
+    ren.set_fs(|f: &FragmentInput<Vertex>| {
+        let c = &f.va.color;
+        Vector::new(c.x, c.y, c.z, 1.0 - f.va.uv.x)
+    });
+    ren.set_sample_count(8);
+    ren.set_multisample_state(MultisampleState::new(true));
+    ren.clear();
+    ren.render(&front);
+    ren.set_multisample_state(MultisampleState::default());
+    ren.render(&back);
+    assert_golden("alpha_to_coverage", &ren.color_image());
+}
+
+// Alpha to coverage is ignored without samples to drop, a nearly
+// transparent quad is still written everywhere
+#[test]
+fn alpha_to_coverage_single_sample() {
+    let mut ren = Renderer::new(WIDTH, HEIGHT);
+    ren.set_vs(|v: &Vertex| VSOutput::new(v.pos.clone(), copy_vertex(v)));
+    ren.set_fs(|_: &FragmentInput<Vertex>| Vector::new(1.0, 0.0, 0.0, 0.1));
+    ren.set_multisample_state(MultisampleState::new(true));
+    ren.clear();
+    ren.render(&texture_quad());
+    assert!(ren.color_image().pixels().all(|p| p.0 == [255, 0, 0]));
+}
+
+// Alpha tested checker in front of a red quad: black texels are discarded
+// and write no depth, so the quad drawn afterwards shows through them
+#[test]
+fn discard() {
+    let front = texture_quad().into_iter()
+        .map(|v| vertex(Vector::point(v.pos.x * 0.8, v.pos.y * 0.8, 0.2), v.uv, green()))
+        .collect::<Vec<_>>();
+    let back = texture_quad().into_iter()
+        .map(|v| vertex(Vector::point(v.pos.x, v.pos.y, 0.5), v.uv, red()))
+        .collect::<Vec<_>>();
+
+    let tex = checker();
+    let mut ren = Renderer::new(WIDTH, HEIGHT);
+    ren.set_vs(|v: &Vertex| VSOutput::new(v.pos.clone(), copy_vertex(v)));
+    ren.set_fs(move |f: &FragmentInput<Vertex>| {
+        let cutout = f.va.color.y > 0.5 && tex.get_color_nearest(f.va.uv.x, f.va.uv.y).x < 0.5;
+        if cutout { None } else { Some(f.va.color.clone()) }
+    });
+    ren.clear();
+    ren.render(&front);
+    ren.render(&back);
+    assert_golden("discard", &ren.color_image());
+}
+
+// Wireframe over flat shading from the barycentrics, one color per
+// primitive. The right triangle is cut by the x = w plane, the cut gets no
+// line as the barycentrics stay those of the whole triangle.
+#[test]
+fn fragment_builtins() {
+    let vertices = [
+        vertex(Vector::point(-0.9, -0.8, 0.0), Vector::zero(), red()),
+        vertex(Vector::point(-0.1, -0.8, 0.0), Vector::zero(), red()),
+        vertex(Vector::point(-0.5, 0.8, 0.0), Vector::zero(), red()),
+        vertex(Vector::point(0.1, -0.8, 0.0), Vector::zero(), red()),
+        vertex(Vector::point(1.8, -0.4, 0.0), Vector::zero(), red()),
+        vertex(Vector::point(0.4, 0.8, 0.0), Vector::zero(), red()),
+    ];
+    let img = render_clip_space(&vertices, |f: &FragmentInput<Vertex>| {
+        let b = &f.barycentrics;
+        if b.x.min(b.y).min(b.z) < 0.05 {
+            Vector::new(1.0, 1.0, 1.0, 1.0)
+        } else if f.primitive_id == 0 {
+            red()
+        } else {
+            blue()
+        }
+    });
+    assert_golden("fragment_builtins", &img);
+}
+
+#[test]
+fn frag_coord() {
+    let mut ren = Renderer::new(WIDTH, HEIGHT);
+    ren.set_vs(|v: &Vertex| VSOutput::new(v.pos.clone(), copy_vertex(v)));
+    ren.set_fs(|f: &FragmentInput<Vertex>| {
+        let c = &f.frag_coord;
+        Vector::new(c.x / WIDTH as f32, c.y / HEIGHT as f32, c.z, c.w)
+    });
+    ren.set_color_format(ColorFormat::Rgba32F);
+    ren.clear();
+    ren.render(&texture_quad());
+    let img = ren.color_image_rgba();
+    for (x, y) in [(0, 0), (10, 20), (63, 40)] {
+        let (fx, fy) = ((x as f32 + 0.5) / WIDTH as f32, (y as f32 + 0.5) / HEIGHT as f32);
+        let byte = |v: f32| (v * 255.0).round() as u8;
+        // z = 0 is in the middle of the depth range, w = 1 / 1
+        assert_eq!([byte(fx), byte(fy), 128, 255], img.get_pixel(x, y).0, "({}, {})", x, y);
+    }
+}
+
+// Sphere impostor: a flat quad whose fragment shader writes the depth of a
+// sphere, cutting through a red plane over the left half. The plane shows
+// around the rim there, where the sphere is behind it.
+#[test]
+fn frag_depth() {
+    let plane = texture_quad().into_iter()
+        .map(|v| vertex(Vector::point(v.pos.x.min(0.0), v.pos.y, 0.0), v.uv, red()))
+        .collect::<Vec<_>>();
+    let impostor = texture_quad().into_iter()
+        .map(|v| vertex(Vector::point(v.pos.x * 0.8, v.pos.y * 0.8, -0.9), v.uv, green()))
+        .collect::<Vec<_>>();
+
+    let mut ren = Renderer::new(WIDTH, HEIGHT);
+    ren.set_vs(|v: &Vertex| VSOutput::new(v.pos.clone(), copy_vertex(v)));
+    ren.set_fs(|f: &FragmentInput<Vertex>| {
+        if f.va.color.y < 0.5 {
+            return Some(FragmentOutput::from(f.va.color.clone()));
+        }
+        let (x, y) = (f.va.uv.x * 2.0 - 1.0, f.va.uv.y * 2.0 - 1.0);
+        let r2 = x * x + y * y;
+        if r2 > 1.0 {
+            return None;
+        }
+        let h = (1.0 - r2).sqrt();
+        Some(FragmentOutput::from(green().scale(h)).with_depth(0.8 - 0.6 * h))
+    });
+    ren.set_fs_writes_depth(true);
+    ren.clear();
+    ren.render(&plane);
+    ren.render(&impostor);
+    assert_golden("frag_depth", &ren.color_image());
+}
+
+// Early-z already culled by the interpolated depth, the written one would be
+// lost without a word
+#[test]
+#[should_panic(expected = "without Shader::writes_depth")]
+fn undeclared_frag_depth() {
+    let mut ren = Renderer::new(WIDTH, HEIGHT);
+    ren.set_vs(|v: &Vertex| VSOutput::new(v.pos.clone(), copy_vertex(v)));
+    ren.set_fs(|_: &FragmentInput<Vertex>| FragmentOutput::from(red()).with_depth(0.5));
+    ren.clear();
+    ren.render(&texture_quad());
+}
+
+// Split screen with the cube seen from two sides, and a minimap in the
+// top-right corner cleared and drawn through the scissor
+#[test]
+fn viewports() {
+    let (data, indices) = scene::cube();
+    // The vertex shader is fixed, every view gets its own clip space positions
+    let view = |angle: f32, aspect: f32| {
+        let pv = scene::orbit_camera(angle, aspect);
+        data.iter().map(|v| vertex(pv.apply(&v.pos), v.uv.clone(), v.color.clone())).collect::<Vec<_>>()
+    };
+    let tex = checker();
+    let mut ren = Renderer::new(WIDTH, HEIGHT);
+    ren.set_vs(|v: &Vertex| VSOutput::new(v.pos.clone(), copy_vertex(v)));
+    ren.set_fs(move |f: &FragmentInput<Vertex>| tex.get_color_linear(f.va.uv.x, f.va.uv.y));
+    ren.clear_color(0.5, 0.8, 1.0);
+    ren.clear();
+
+    let half = WIDTH as f32 / 2.0;
+    for &(x, angle) in &[(0.0, 0.5), (half, 2.5)] {
+        ren.set_viewport(Viewport::new(x, 0.0, half, HEIGHT as f32));
+        ren.render_with_index(&view(angle, half / HEIGHT as f32), &indices);
+    }
+
+    ren.set_scissor(44, 4, 16, 16);
+    ren.clear_color(0.1, 0.1, 0.1);
+    ren.clear();
+    // Larger than the scissor, the border is cut off
+    ren.set_viewport(Viewport::new(40.0, 0.0, 24.0, 24.0));
+    ren.render_with_index_parallel(&view(3.1, 1.0), &indices);
+    assert_golden("viewports", &ren.color_image());
+}
+
+#[test]
+fn scissor() {
+    let mut ren = Renderer::new(16, 16);
+    ren.set_vs(|v: &Vertex| VSOutput::new(v.pos.clone(), copy_vertex(v)));
+    ren.set_fs(vertex_color);
+    ren.set_supersampling(2, DownsampleFilter::Box);
+    ren.clear();
+    ren.set_scissor(3, 5, 4, 2);
+    ren.render(&texture_quad());
+    let img = ren.color_image();
+    for y in 0..16 {
+        for x in 0..16 {
+            let inside = (3..7).contains(&x) && (5..7).contains(&y);
+            assert_eq!(if inside { [255, 0, 0] } else { [0, 0, 0] }, img.get_pixel(x, y).0, "({}, {})", x, y);
+        }
+    }
+}
+
+// Half size quad in one quadrant, Uniforms is its center in clip space
+struct CheckerMaterial {
+    texture: Texture,
+}
+
+impl Shader<Vertex> for CheckerMaterial {
+    type Uniforms = (f32, f32);
+    type Output = Vector;
+
+    fn vertex(&self, &(x, y): &(f32, f32), v: &Vertex) -> VSOutput<Vertex> {
+        let pos = Vector::point(v.pos.x * 0.5 + x, v.pos.y * 0.5 + y, v.pos.z);
+        VSOutput::new(pos, copy_vertex(v))
+    }
+
+    fn fragment(&self, _: &(f32, f32), f: &FragmentInput<Vertex>) -> Vector {
+        self.texture.get_color_nearest(f.va.uv.x, f.va.uv.y)
+    }
+}
+
+// Same as CheckerMaterial, filled with the color in Uniforms
+struct SolidMaterial;
+
+impl Shader<Vertex> for SolidMaterial {
+    type Uniforms = ((f32, f32), Vector);
+    type Output = Vector;
+
+    fn vertex(&self, &((x, y), _): &Self::Uniforms, v: &Vertex) -> VSOutput<Vertex> {
+        let pos = Vector::point(v.pos.x * 0.5 + x, v.pos.y * 0.5 + y, v.pos.z);
+        VSOutput::new(pos, copy_vertex(v))
+    }
+
+    fn fragment(&self, (_, color): &Self::Uniforms, _: &FragmentInput<Vertex>) -> Vector {
+        color.clone()
+    }
+}
+
+// Two materials and three uniform sets drawn into one renderer, through
+// generic and dyn draw calls
+#[test]
+fn shader_materials() {
+    let checker = CheckerMaterial { texture: checker() };
+    let solid: &(dyn Shader<Vertex, Uniforms = ((f32, f32), Vector), Output = Vector> + Sync) = &SolidMaterial;
+    let quad = texture_quad();
+
+    let mut ren = ShaderRenderer::new(WIDTH, HEIGHT);
+    ren.clear_color(0.2, 0.2, 0.2);
+    ren.clear();
+    ren.draw(&checker, &(-0.5, 0.5), &quad);
+    ren.draw_parallel(&checker, &(0.5, -0.5), &quad);
+    ren.draw_with_index(solid, &((0.5, 0.5), green()), &quad[..4], &[0, 1, 2]);
+    ren.draw_with_index_parallel(solid, &((-0.5, -0.5), blue()), &quad, &[0, 1, 2, 3, 4, 5]);
+    assert_golden("shader_materials", &ren.color_image());
+}
+
+// The output size follows resize, supersampling and the sample count are kept
+#[test]
+fn resize() {
+    let mut ren = Renderer::new(16, 16);
+    ren.set_vs(|v: &Vertex| VSOutput::new(v.pos.clone(), copy_vertex(v)));
+    ren.set_fs(vertex_color);
+    ren.set_supersampling(2, DownsampleFilter::Box);
+    ren.set_sample_count(4);
+    ren.resize(24, 8);
+    assert_eq!((24, 8), (ren.width(), ren.height()));
+    ren.clear();
+    ren.render(&texture_quad());
+    let img = ren.color_image();
+    assert_eq!((24, 8), img.dimensions());
+    assert!(img.pixels().all(|p| p.0 == [255, 0, 0]));
+    let mut len = 0;
+    ren.get_color_buffer(|buf| len = buf.len());
+    assert_eq!(24 * 8 * 3, len);
+}
+
+// A minimized window resizes to 0, drawing and presenting still work
+#[test]
+fn empty_target() {
+    let mut ren = Renderer::new(0, 16);
+    ren.set_vs(|v: &Vertex| VSOutput::new(v.pos.clone(), copy_vertex(v)));
+    ren.set_fs(vertex_color);
+    for &(w, h) in [(0, 16), (16, 0), (0, 0)].iter() {
+        ren.resize(w, h);
+        ren.set_sample_count(4);
+        ren.clear();
+        ren.render(&texture_quad());
+        ren.render_parallel(&texture_quad());
+        assert_eq!((w as u32, h as u32), ren.color_image().dimensions());
+        ren.get_color_buffer(|buf| assert!(buf.is_empty()));
+    }
+}
+
+// The RGB24 of get_color_buffer is converted into the same buffer every
+// frame, supersampled too
+#[test]
+fn color_buffer_reused() {
+    for ssaa in [1, 2] {
+        let mut ren = Renderer::new(WIDTH, HEIGHT);
+        ren.set_vs(|v: &Vertex| VSOutput::new(v.pos.clone(), copy_vertex(v)));
+        ren.set_fs(vertex_color);
+        ren.set_supersampling(ssaa, DownsampleFilter::Tent);
+        let mut frames = Vec::new();
+        for clear in [0.0, 1.0] {
+            ren.clear_color(clear, clear, clear);
+            ren.clear();
+            ren.get_color_buffer(|buf| frames.push((buf.as_ptr(), buf.len(), buf[0])));
+        }
+        assert_eq!(frames[0].0, frames[1].0);
+        assert_eq!([(WIDTH * HEIGHT * 3, 0), (WIDTH * HEIGHT * 3, 255)], [(frames[0].1, frames[0].2), (frames[1].1, frames[1].2)]);
+    }
+}
+
+// Left triangle is counter-clockwise, right one clockwise. Front faces are
+// shaded red and back faces blue.
+#[test]
+fn cull_modes() {
+    let vertices = [
+        vertex(Vector::point(-0.9, -0.5, 0.0), Vector::zero(), red()),
+        vertex(Vector::point(-0.1, -0.5, 0.0), Vector::zero(), red()),
+        vertex(Vector::point(-0.5, 0.5, 0.0), Vector::zero(), red()),
+        vertex(Vector::point(0.1, -0.5, 0.0), Vector::zero(), red()),
+        vertex(Vector::point(0.5, 0.5, 0.0), Vector::zero(), red()),
+        vertex(Vector::point(0.9, -0.5, 0.0), Vector::zero(), red()),
+    ];
+    let draw = |state: RasterizerState| {
+        let mut ren = Renderer::new(WIDTH, HEIGHT);
+        ren.set_vs(|v: &Vertex| VSOutput::new(v.pos.clone(), copy_vertex(v)));
+        ren.set_fs(|f: &FragmentInput<Vertex>| if f.front_facing { red() } else { blue() });
+        ren.set_rasterizer_state(state);
+        ren.clear();
+        ren.render(&vertices);
+        let img = ren.color_image();
+        (img.get_pixel(16, 36).0, img.get_pixel(48, 36).0)
+    };
+    let (r, b, k) = ([255, 0, 0], [0, 0, 255], [0, 0, 0]);
+
+    assert_eq!((r, k), draw(RasterizerState::default()));
+    assert_eq!((r, b), draw(RasterizerState::double_sided()));
+    assert_eq!((k, b), draw(RasterizerState::new(CullMode::Front, FrontFace::Ccw)));
+    assert_eq!((k, k), draw(RasterizerState::new(CullMode::FrontAndBack, FrontFace::Ccw)));
+    assert_eq!((k, r), draw(RasterizerState::new(CullMode::Back, FrontFace::Cw)));
+    assert_eq!((b, r), draw(RasterizerState::new(CullMode::None, FrontFace::Cw)));
+}
+
+// Without culling the inner faces of the cube show through the near plane
+// cut, back faces are shaded darker
+#[test]
+fn double_sided_cut_cube() {
+    let tex = checker();
+    let (data, indices) = scene::cube();
+    // The near plane is far enough to cut the corner of the cube facing the camera
+    let projection = Matrix::perspective(std::f32::consts::PI * 0.5, 1.0, 1.6, 100.0);
+    let view = Matrix::look_at(&Vector::point(-0.5, 1.0, 2.0), &Vector::point(0.0, 0.0, 0.0), &Vector::vec(0.0, 1.0, 0.0));
+    let pv = &projection * &view;
+
+    let mut ren = Renderer::new(WIDTH, HEIGHT);
+    ren.set_vs(move |v: &Vertex| VSOutput::new(pv.apply(&v.pos), copy_vertex(v)));
+    ren.set_fs(move |f: &FragmentInput<Vertex>| {
+        let c = tex.get_color_nearest(f.va.uv.x, f.va.uv.y);
+        if f.front_facing { c } else { c.scale(0.4) }
+    });
+    ren.set_rasterizer_state(RasterizerState::double_sided());
+    ren.clear_color(0.5, 0.8, 1.0);
+    ren.clear();
+    ren.render_with_index(&data, &indices);
+    assert_golden("double_sided_cut_cube", &ren.color_image());
+}
+
+// Floor grid as a line list running out of the view, the cube edges with
+// their vertex colors and its corners as points
+#[test]
+fn debug_lines() {
+    let (cube, _) = scene::cube();
+    let pv = scene::orbit_camera(0.5, 1.0);
+    let mut grid = vec![];
+    for i in -4..=4 {
+        let c = i as f32;
+        let gray = Vector::new(0.4, 0.4, 0.4, 1.0);
+        grid.push(vertex(Vector::point(c * 0.5, -0.5, -2.0), Vector::zero(), gray.clone()));
+        grid.push(vertex(Vector::point(c * 0.5, -0.5, 2.0), Vector::zero(), gray.clone()));
+        grid.push(vertex(Vector::point(-2.0, -0.5, c * 0.5), Vector::zero(), gray.clone()));
+        grid.push(vertex(Vector::point(2.0, -0.5, c * 0.5), Vector::zero(), gray));
+    }
+    let colored: Vec<Vertex> = cube.iter().map(|v| {
+        let c = Vector::new(v.pos.x * 0.5 + 0.5, v.pos.y * 0.5 + 0.5, v.pos.z * 0.5 + 0.5, 1.0);
+        vertex(Vector::point(v.pos.x * 0.5, v.pos.y * 0.5, v.pos.z * 0.5), Vector::zero(), c)
+    }).collect();
+    let corners: Vec<Vertex> = colored.iter().map(|v| vertex(v.pos.clone(), Vector::zero(), Vector::new(1.0, 1.0, 1.0, 1.0))).collect();
+    let edges = [0, 1, 1, 2, 2, 3, 3, 0, 4, 5, 5, 6, 6, 7, 7, 4, 0, 4, 1, 7, 2, 6, 3, 5];
+
+    let mut ren = Renderer::new(WIDTH, HEIGHT);
+    ren.set_vs(move |v: &Vertex| VSOutput::new(pv.apply(&v.pos), copy_vertex(v)));
+    ren.set_fs(vertex_color);
+    ren.clear();
+    ren.set_primitive_topology(PrimitiveTopology::LineList);
+    ren.render(&grid);
+    ren.render_with_index(&colored, &edges);
+    ren.set_primitive_topology(PrimitiveTopology::PointList);
+    ren.set_depth_state(DepthState::disabled());
+    ren.render(&corners);
+    assert_golden("debug_lines", &ren.color_image());
+}
+
+// A 3x2 quad grid drawn as a strip and a fan of the same triangles has to
+// match the triangle list, windings included
+#[test]
+fn strip_and_fan_match_list() {
+    let p = |x: f32, y: f32| vertex(Vector::point(x, y, 0.0), Vector::vec2(x, y), Vector::new(x * 0.5 + 0.5, y * 0.5 + 0.5, 0.5, 1.0));
+    let draw = |topology: PrimitiveTopology, vertices: &[Vertex]| {
+        let mut ren = Renderer::new(WIDTH, HEIGHT);
+        ren.set_vs(|v: &Vertex| VSOutput::new(v.pos.clone(), copy_vertex(v)));
+        ren.set_fs(vertex_color);
+        ren.set_primitive_topology(topology);
+        ren.clear();
+        ren.render(vertices);
+        ren.color_image().into_raw()
+    };
+
+    let xs = [-0.9, -0.3, 0.3, 0.9];
+    let strip: Vec<Vertex> = xs.iter().flat_map(|&x| vec![p(x, 0.7), p(x, -0.7)]).collect();
+    let strip_list: Vec<Vertex> = xs.windows(2).flat_map(|w| vec![
+        p(w[0], 0.7), p(w[0], -0.7), p(w[1], 0.7),
+        p(w[1], 0.7), p(w[0], -0.7), p(w[1], -0.7),
+    ]).collect();
+    let empty = draw(PrimitiveTopology::TriangleList, &[]);
+    let strip_image = draw(PrimitiveTopology::TriangleStrip, &strip);
+    assert_ne!(strip_image, empty);
+    assert_eq!(strip_image, draw(PrimitiveTopology::TriangleList, &strip_list));
+
+    let rim = [(0.8, 0.0), (0.4, 0.7), (-0.4, 0.7), (-0.8, 0.0), (-0.4, -0.7)];
+    let fan: Vec<Vertex> = std::iter::once(p(0.0, 0.0)).chain(rim.iter().map(|&(x, y)| p(x, y))).collect();
+    let fan_list: Vec<Vertex> = rim.windows(2).flat_map(|w| vec![p(0.0, 0.0), p(w[0].0, w[0].1), p(w[1].0, w[1].1)]).collect();
+    let fan_image = draw(PrimitiveTopology::TriangleFan, &fan);
+    assert_ne!(fan_image, empty);
+    assert_eq!(fan_image, draw(PrimitiveTopology::TriangleList, &fan_list));
+}
+
+// Opaque background, a half transparent red triangle blended over it, an
+// additive green one and a multiplied one on top, each nearer than the last
+#[test]
+fn blending() {
+    let tri = |x: f32, y: f32, z: f32, color: Vector| vec![
+        vertex(Vector::point(x - 0.6, y - 0.5, z), Vector::zero(), color.clone()),
+        vertex(Vector::point(x + 0.6, y - 0.5, z), Vector::zero(), color.clone()),
+        vertex(Vector::point(x, y + 0.6, z), Vector::zero(), color),
+    ];
+
+    let mut ren = Renderer::new(WIDTH, HEIGHT);
+    ren.set_vs(|v: &Vertex| VSOutput::new(v.pos.clone(), copy_vertex(v)));
+    ren.set_fs(vertex_color);
+    ren.clear_color(0.0, 0.0, 0.5);
+    ren.clear();
+
+    ren.set_blend_state(BlendState::alpha());
+    ren.render(&tri(-0.3, 0.2, 0.5, Vector::new(1.0, 0.0, 0.0, 0.5)));
+    ren.set_blend_state(BlendState::additive());
+    ren.render(&tri(0.3, 0.2, 0.0, Vector::new(0.0, 1.0, 0.0, 0.75)));
+    ren.set_blend_state(BlendState::new(BlendFactor::DstColor, BlendFactor::Zero, BlendOp::Add));
+    ren.render(&tri(0.0, -0.3, -0.5, Vector::new(1.0, 0.5, 1.0, 1.0)));
+    assert_golden("blending", &ren.color_image());
+}
+
+fn flat_triangle(z: f32, color: Vector) -> Vec<Vertex> {
+    vec![
+        vertex(Vector::point(-0.8, -0.8, z), Vector::zero(), color.clone()),
+        vertex(Vector::point(0.8, -0.8, z), Vector::zero(), color.clone()),
+        vertex(Vector::point(0.0, 0.8, z), Vector::zero(), color),
+    ]
+}
+
+fn center_pixel(img: &RgbImage) -> [u8; 3] {
+    img.get_pixel(WIDTH as u32 / 2, HEIGHT as u32 / 2).0
+}
+
+#[test]
+fn depth_funcs() {
+    let draw = |depth: DepthState| {
+        let mut ren = Renderer::new(WIDTH, HEIGHT);
+        ren.set_vs(|v: &Vertex| VSOutput::new(v.pos.clone(), copy_vertex(v)));
+        ren.set_fs(vertex_color);
+        ren.clear();
+        ren.render(&flat_triangle(0.0, red()));
+        ren.set_depth_state(depth);
+        ren.render(&flat_triangle(0.0, blue()));
+        center_pixel(&ren.color_image())
+    };
+
+    // Decal on the same depth
+    assert_eq!([255, 0, 0], draw(DepthState::new(CompareFunc::Less, true)));
+    assert_eq!([0, 0, 255], draw(DepthState::new(CompareFunc::LessEqual, true)));
+    assert_eq!([0, 0, 255], draw(DepthState::new(CompareFunc::Equal, true)));
+    assert_eq!([255, 0, 0], draw(DepthState::new(CompareFunc::Never, true)));
+    assert_eq!([0, 0, 255], draw(DepthState::disabled()));
+}
+
+#[test]
+fn depth_write_mask() {
+    let mut ren = Renderer::new(WIDTH, HEIGHT);
+    ren.set_vs(|v: &Vertex| VSOutput::new(v.pos.clone(), copy_vertex(v)));
+    ren.set_fs(vertex_color);
+    ren.clear();
+
+    // Depth pre-pass leaves the color alone, the far triangle is hidden by it
+    ren.set_blend_state(BlendState::new(BlendFactor::Zero, BlendFactor::One, BlendOp::Add));
+    ren.render(&flat_triangle(-0.5, green()));
+    ren.set_blend_state(BlendState::default());
+    ren.render(&flat_triangle(0.5, red()));
+    assert_eq!([0, 0, 0], center_pixel(&ren.color_image()));
+
+    // Without depth writes the near triangle no longer hides the far one
+    ren.clear();
+    ren.set_depth_state(DepthState::new(CompareFunc::Less, false));
+    ren.render(&flat_triangle(-0.5, green()));
+    ren.render(&flat_triangle(0.5, red()));
+    assert_eq!([255, 0, 0], center_pixel(&ren.color_image()));
+}
+
+#[test]
+fn reverse_z_matches_forward() {
+    let (data, indices) = scene::cube();
+    let render = |reverse: bool| {
+        let tex = checker();
+        let pv = scene::orbit_camera(0.5, 1.0);
+        let mut ren = Renderer::new(WIDTH, HEIGHT);
+        ren.set_vs(move |v: &Vertex| VSOutput::new(pv.apply(&v.pos), copy_vertex(v)));
+        ren.set_fs(move |f: &FragmentInput<Vertex>| tex.get_color_linear(f.va.uv.x, f.va.uv.y));
+        if reverse {
+            ren.set_depth_range(1.0, 0.0);
+            ren.clear_depth(0.0);
+            ren.set_depth_state(DepthState::new(CompareFunc::Greater, true));
+        }
+        ren.clear();
+        ren.render_with_index(&data, &indices);
+        ren.color_image()
+    };
+    assert!(render(false).into_raw() == render(true).into_raw());
+}
+
+// Two overlapping triangles count into the stencil buffer without touching
+// the color, then full-screen passes color the pixels by their count
+#[test]
+fn stencil() {
+    let tri = |x: f32| vec![
+        vertex(Vector::point(x - 0.5, -0.6, 0.0), Vector::zero(), red()),
+        vertex(Vector::point(x + 0.5, -0.6, 0.0), Vector::zero(), red()),
+        vertex(Vector::point(x, 0.6, 0.0), Vector::zero(), red()),
+    ];
+    let quad = |color: Vector| texture_quad().into_iter()
+        .map(|v| vertex(v.pos, v.uv, color.clone()))
+        .collect::<Vec<_>>();
+
+    let mut ren = Renderer::new(WIDTH, HEIGHT);
+    ren.set_vs(|v: &Vertex| VSOutput::new(v.pos.clone(), copy_vertex(v)));
+    ren.set_fs(vertex_color);
+    ren.clear_stencil(0);
+    ren.clear();
+
+    ren.set_depth_state(DepthState::disabled());
+    ren.set_blend_state(BlendState::new(BlendFactor::Zero, BlendFactor::One, BlendOp::Add));
+    ren.set_stencil_state(StencilState::new(
+        StencilFaceState::new(CompareFunc::Always, 0).with_ops(StencilOp::Keep, StencilOp::Keep, StencilOp::IncrClamp)));
+    ren.render(&tri(-0.2));
+    ren.render(&tri(0.2));
+
+    ren.set_blend_state(BlendState::default());
+    ren.set_stencil_state(StencilState::new(StencilFaceState::new(CompareFunc::Equal, 1)));
+    ren.render(&quad(red()));
+    ren.set_stencil_state(StencilState::new(StencilFaceState::new(CompareFunc::Equal, 2)));
+    ren.render(&quad(green()));
+    // Only pixels still at 0 pass `0 == stencil`, and they are set to 3
+    ren.set_stencil_state(StencilState::new(
+        StencilFaceState::new(CompareFunc::Equal, 0).with_ops(StencilOp::Keep, StencilOp::Keep, StencilOp::Invert).with_masks(0xff, 0x03)));
+    ren.render(&quad(blue()));
+    ren.set_stencil_state(StencilState::new(StencilFaceState::new(CompareFunc::Equal, 3)));
+    ren.set_blend_state(BlendState::alpha());
+    ren.render(&quad(Vector::new(1.0, 1.0, 1.0, 0.5)));
+    assert_golden("stencil", &ren.color_image());
+}
+
+// The tiled multithreaded path has to give the same pixels as the single
+// threaded one, the size is not a multiple of the tile size on purpose
+#[test]
+fn parallel_matches_single_thread() {
+    let (w, h) = (203, 141);
+    let (data, indices) = scene::cube();
+    let pv = scene::orbit_camera(0.8, w as f32 / h as f32);
+
+    let render = |parallel: bool| {
+        let tex = checker();
+        let pv = pv.clone();
+        let mut ren = Renderer::new(w, h);
+        ren.set_vs(move |v: &Vertex| VSOutput::new(pv.apply(&v.pos), copy_vertex(v)));
+        ren.set_fs(move |f: &FragmentInput<Vertex>| tex.get_color_linear(f.va.uv.x, f.va.uv.y));
+        ren.set_threads(4);
+        ren.clear_color(0.5, 0.8, 1.0);
+        ren.clear();
+        if parallel {
+            ren.render_with_index_parallel(&data, &indices);
+        } else {
+            ren.render_with_index(&data, &indices);
+        }
+        ren.color_image()
+    };
+
+    let single = render(false);
+    let parallel = render(true);
+    assert!(single.into_raw() == parallel.into_raw());
+}